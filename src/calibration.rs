@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use ecolor::{gamma_from_linear, Rgba};
+use serde::Deserialize;
+
+use crate::LedMappingInfo;
+
+/// Entries in each lookup table, the levels in between are interpolated
+const LUT_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ColorProfileSettings {
+    gamma: f32,
+    /// Multiplies each channel after gamma, to match white points between batches
+    gain: [f32; 3],
+}
+
+impl Default for ColorProfileSettings {
+    fn default() -> Self {
+        Self {
+            gamma: 2.2,
+            gain: [1.0; 3],
+        }
+    }
+}
+
+/// Output color correction for a batch of leds, done with a lookup table per channel.
+/// The tables hold float levels, so the output can still be dithered.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "ColorProfileSettings")]
+pub struct ColorProfile {
+    /// Indexed by the linear channel value
+    lut: [Box<[f32]>; 3],
+}
+
+impl TryFrom<ColorProfileSettings> for ColorProfile {
+    type Error = String;
+
+    fn try_from(settings: ColorProfileSettings) -> Result<Self, Self::Error> {
+        if settings.gamma <= 0.0 {
+            return Err(format!("gamma has to be above 0, not {}", settings.gamma));
+        }
+
+        if settings.gain.iter().any(|gain| *gain < 0.0) {
+            return Err(format!("gain can't be negative, got {:?}", settings.gain));
+        }
+
+        Ok(Self::new(settings))
+    }
+}
+
+impl ColorProfile {
+    fn new(ColorProfileSettings { gamma, gain }: ColorProfileSettings) -> Self {
+        let lut = gain.map(|gain| {
+            (0..LUT_SIZE)
+                .map(|i| {
+                    let encoded = gamma_from_linear(i as f32 / (LUT_SIZE - 1) as f32);
+                    (encoded.powf(gamma) * gain).clamp(0.0, 1.0)
+                })
+                .collect()
+        });
+
+        Self { lut }
+    }
+}
+
+impl Default for ColorProfile {
+    fn default() -> Self {
+        Self::new(ColorProfileSettings::default())
+    }
+}
+
+impl ColorProfile {
+    /// Corrected output level of each channel in the range 0..1
+    pub fn apply(&self, color: Rgba) -> [f32; 3] {
+        let rgb = [color.r(), color.g(), color.b()];
+
+        std::array::from_fn(|channel| {
+            let lut = &self.lut[channel];
+            let pos = rgb[channel].clamp(0.0, 1.0) * (LUT_SIZE - 1) as f32;
+            let i = (pos as usize).min(LUT_SIZE - 2);
+            let t = pos - i as f32;
+
+            lut[i] * (1.0 - t) + lut[i + 1] * t
+        })
+    }
+}
+
+/// The color profiles and which fixtures use them
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    /// Used by fixtures without a profile
+    default: ColorProfile,
+    profiles: HashMap<String, ColorProfile>,
+    /// Fixture name or group to profile name
+    fixtures: HashMap<String, String>,
+}
+
+impl Calibration {
+    /// Every fixture has to use a profile that exists
+    pub fn validate(&self) -> Result<(), String> {
+        match self.fixtures.iter().find(|(_, profile)| !self.profiles.contains_key(*profile)) {
+            Some((fixture, profile)) => Err(format!("{fixture:?} uses the color profile {profile:?}, but there is no such profile")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn profile_for(&self, fixture: &LedMappingInfo) -> &ColorProfile {
+        std::iter::once(&fixture.name)
+            .chain(&fixture.groups)
+            .filter_map(|key| self.fixtures.get(key))
+            .find_map(|profile| self.profiles.get(profile))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Color32;
+
+    use super::{ColorProfile, ColorProfileSettings};
+
+    #[test]
+    fn correction() {
+        let profile = ColorProfile::try_from(ColorProfileSettings { gamma: 2.0, gain: [1.0, 0.5, 2.0] }).unwrap();
+        let assert_levels = |color: Color32, expected: [f32; 3]| {
+            let levels = profile.apply(color.into());
+            assert!(levels.iter().zip(expected).all(|(level, expected)| (level - expected).abs() < 1e-3), "{levels:?}");
+        };
+
+        assert_levels(Color32::from_rgb(255, 255, 255), [1.0, 0.5, 1.0]);
+        assert_levels(Color32::from_rgb(0, 0, 0), [0.0, 0.0, 0.0]);
+        assert_levels(Color32::from_rgb(128, 128, 128), [0.252, 0.126, 0.504]);
+
+        assert!(toml::from_str::<ColorProfile>("gamma = 0").is_err());
+        assert!(toml::from_str::<ColorProfile>("gain = [1.0, -0.5, 1.0]").is_err());
+        assert!(toml::from_str::<ColorProfile>("gama = 2.4").is_err());
+        assert!(toml::from_str::<ColorProfile>("gamma = 2.4").is_ok());
+    }
+}
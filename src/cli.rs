@@ -1,9 +1,60 @@
-use clap::{Parser, arg, command};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, arg, command};
+use glam::Vec2;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
    /// Run without the UI
    #[arg(long)]
-   pub headless: bool
-}
\ No newline at end of file
+   pub headless: bool,
+
+   /// Load the fixtures from an xLights layout (.xml, .xmodel) or a custom grid file instead of the built in layout
+   #[arg(long)]
+   pub layout: Option<PathBuf>,
+
+   /// Settings like color calibration, see config.rs
+   #[arg(long)]
+   pub config: Option<PathBuf>,
+
+   #[command(subcommand)]
+   pub command: Option<Command>
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+   /// Write a wiring diagram of the layout and exit
+   Diagram {
+      /// Where to write the svg
+      #[arg(default_value = "layout.svg")]
+      output: PathBuf,
+
+      /// Also write a png
+      #[cfg(feature = "png")]
+      #[arg(long)]
+      png: Option<PathBuf>
+   },
+
+   /// Find the fixture pixels at canvas positions and exit
+   Find {
+      /// The pixel nearest to x,y
+      #[arg(long, value_parser = parse_point, allow_hyphen_values = true)]
+      at: Option<Vec2>,
+
+      /// Every pixel within this many canvas units of --at instead of the nearest one
+      #[arg(long, requires = "at")]
+      radius: Option<f32>,
+
+      /// Every pixel inside the polygon made by repeating --polygon x,y
+      #[arg(long, value_parser = parse_point, allow_hyphen_values = true, conflicts_with = "at")]
+      polygon: Vec<Vec2>
+   }
+}
+
+fn parse_point(point: &str) -> Result<Vec2, String> {
+   let (x, y) = point.split_once(',').ok_or("Expected a point like 1.5,-2")?;
+   let parse = |value: &str| value.trim().parse::<f32>().map_err(|err| format!("{value:?}: {err}"));
+
+   Ok(Vec2::new(parse(x)?, parse(y)?))
+}
@@ -0,0 +1,173 @@
+use std::{collections::BTreeMap, fmt::Display, path::Path};
+
+use serde::Deserialize;
+
+use crate::{calibration::Calibration, dither::DitherSettings, groups::EffectsConfig, palettes::{PaletteSettings, PaletteSpec}, playlist::PlaylistConfig, power::PowerLimiter, scene::SceneSettings};
+
+/// Settings loaded from a toml file, everything is optional
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub calibration: Calibration,
+    pub power: PowerLimiter,
+    pub dithering: DitherSettings,
+    /// Replaces the built in effect stacks of the "default" scene
+    pub effects: Option<EffectsConfig>,
+    pub scene: SceneSettings,
+    /// Effect stacks of the other scenes by name, in the order of their names
+    pub scenes: BTreeMap<String, EffectsConfig>,
+    /// Cycles through scenes while nobody is talking
+    pub playlist: Option<PlaylistConfig>,
+    pub palette: PaletteSettings,
+    /// Palettes to pick from besides the built in ones, in the order of their names
+    pub palettes: BTreeMap<String, PaletteSpec>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    /// Parsed fine, but doesn't fit together
+    Invalid(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "Could not read the config file: {err}"),
+            ConfigError::Toml(err) => write!(f, "Invalid config: {err}"),
+            ConfigError::Invalid(err) => write!(f, "Invalid config: {err}"),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let mut config: Self = toml::from_str(&text).map_err(ConfigError::Toml)?;
+
+        config.resolve_paths(path.parent().unwrap_or(Path::new(""))).map_err(ConfigError::Invalid)?;
+        config.validate().map_err(ConfigError::Invalid)?;
+        Ok(config)
+    }
+
+    /// Makes the paths of files the config uses relative to the config instead of the
+    /// working directory
+    fn resolve_paths(&mut self, dir: &Path) -> Result<(), String> {
+        for spec in self.palettes.values_mut() {
+            if let PaletteSpec::File(path) = spec {
+                *path = dir.join(&path);
+            }
+        }
+
+        for (name, effects) in self.effects.iter_mut().map(|effects| ("default", effects))
+            .chain(self.scenes.iter_mut().map(|(name, effects)| (name.as_str(), effects)))
+        {
+            effects.resolve_paths(dir).map_err(|err| format!("{err}, used in the {name} scene"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the parts of the config that refer to each other
+    fn validate(&self) -> Result<(), String> {
+        self.calibration.validate()?;
+
+        if self.effects.is_some() && self.scenes.contains_key("default") {
+            return Err("[effects] and [scenes.default] both set the default scene, only one of them can be used".to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Color32;
+
+    use super::Config;
+    use crate::{strip_mapping::StripMapping, LedMappingInfo};
+
+    #[test]
+    fn calibration() {
+        let config: Config = toml::from_str(r#"
+            [calibration.default]
+            gamma = 2.4
+
+            [calibration.profiles.batch_b]
+            gain = [1.0, 0.9, 0.8]
+
+            [calibration.fixtures]
+            mouth = "batch_b"
+        "#).unwrap();
+
+        let fixture = |group| LedMappingInfo::new(StripMapping::new(1, false).into(), Default::default(), (0, 0).into())
+            .with_group(group);
+
+        let assert_levels = |group, color: Color32, expected: [f32; 3]| {
+            let levels = config.calibration.profile_for(&fixture(group)).apply(color.into());
+            assert!(levels.iter().zip(expected).all(|(level, expected)| (level - expected).abs() < 1e-3), "{levels:?}");
+        };
+
+        assert_levels("mouth", Color32::WHITE, [1.0, 0.9, 0.8]);
+        assert_levels("jaw", Color32::from_gray(128), [0.191; 3]);
+
+        assert!(config.validate().is_ok());
+        assert!(toml::from_str::<Config>("speed = 1").is_err());
+
+        let config: Config = toml::from_str(r#"
+            [calibration.fixtures]
+            mouth = "batch_c"
+        "#).unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn effects() {
+        let config: Config = toml::from_str(r#"
+            [effects]
+            default = ["blobs", { effect = "noise_glow", speed = 0.5 }]
+
+            [[effects.groups]]
+            group = "jaw_strips"
+            stack = ["lightning", { effect = "blobs", blend = "screen", opacity = 0.5, mask = "noise_glow", speed = 2 }]
+        "#).unwrap();
+
+        assert!(config.effects.unwrap().build().is_ok());
+
+        let config: Config = toml::from_str(r#"
+            [effects]
+            default = [{ effect = "blobs", size = 2.0 }]
+        "#).unwrap();
+
+        assert!(config.effects.unwrap().build().is_err());
+
+        let config: Config = toml::from_str(r#"
+            [effects]
+            default = ["blobs"]
+
+            [scenes.default]
+            default = ["lightning"]
+        "#).unwrap();
+
+        assert!(config.validate().is_err());
+
+        // scripts are found next to the config, and missing ones fail right away
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut config: Config = toml::from_str(r#"
+            [effects]
+            default = ["blobs", "effects/ripple.rhai"]
+        "#).unwrap();
+
+        assert!(config.resolve_paths(dir).is_ok());
+        assert!(config.effects.unwrap().build().is_ok());
+
+        let mut config: Config = toml::from_str(r#"
+            [scenes.idle]
+            default = [{ effect = "blobs", mask = "effects/missing.rhai" }]
+        "#).unwrap();
+
+        assert!(config.resolve_paths(dir).is_err());
+    }
+}
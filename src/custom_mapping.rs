@@ -0,0 +1,39 @@
+use glam::{Vec2, UVec2};
+
+use crate::mapping::*;
+
+/// A fixture with an arbitrary pixel layout on a grid, like an xLights custom model
+#[derive(Debug, Clone)]
+pub struct CustomMapping {
+    /// Grid position of each pixel, in dmx order
+    positions: Vec<UPos>,
+    size: UVec2,
+    pitch: f32,
+}
+
+impl CustomMapping {
+    pub fn new(positions: Vec<UPos>, pitch: f32) -> Self {
+        let size = positions.iter()
+            .fold(UVec2::ZERO, |size, pos| size.max(*pos + UVec2::ONE));
+
+        Self { positions, size, pitch }
+    }
+}
+
+impl LedMappingTrait for CustomMapping {
+    fn get_grid_pos(&self, index: LedIndex) -> UPos {
+        self.positions[index]
+    }
+
+    fn get_pitch(&self) -> Vec2 {
+        Vec2::splat(self.pitch)
+    }
+
+    fn get_size(&self) -> UVec2 {
+        self.size
+    }
+
+    fn get_num_pixels(&self) -> usize {
+        self.positions.len()
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::Write;
+
+use glam::Vec2;
+
+use crate::{mapping::LedMappingTrait, pixel_mask::PixelState, LedMappingInfo};
+
+/// Space around the fixtures in mm
+const MARGIN: f32 = 40.0;
+
+fn points(positions: impl IntoIterator<Item=Vec2>) -> String {
+    positions.into_iter()
+        .map(|pos| format!("{:.1},{:.1}", pos.x, pos.y))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Escape text from the config or a layout file for use in the svg
+fn escape(text: &str) -> String {
+    text.chars().fold(String::with_capacity(text.len()), |mut escaped, c| {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+        escaped
+    })
+}
+
+/// Draw a wiring diagram of the fixtures for installing them.
+/// The svg is in mm, with the data path of each fixture drawn from its first pixel onwards.
+pub fn wiring_svg(fixtures: &[LedMappingInfo]) -> Result<String, String> {
+    if fixtures.is_empty() {
+        return Err("The layout has no fixtures to draw".into());
+    }
+
+    let (min, max) = fixtures.iter()
+        .flat_map(|fixture| fixture.world_outline())
+        .fold((Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)), |(min, max), corner| (min.min(corner), max.max(corner)));
+
+    let min = min - Vec2::splat(MARGIN);
+    let size = max - min + Vec2::splat(MARGIN);
+
+    let mut svg = String::new();
+
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}mm" height="{h:.0}mm" viewBox="{x:.1} {y:.1} {w:.1} {h:.1}" font-family="sans-serif">"#,
+        x = min.x, y = min.y, w = size.x, h = size.y
+    ).unwrap();
+
+    svg.push_str(r##"<defs><marker id="arrow" viewBox="0 0 10 10" refX="5" refY="5" markerWidth="3" markerHeight="3" orient="auto"><path d="M0,0 L10,5 L0,10 z" fill="#1565c0"/></marker></defs>"##);
+    svg.push('\n');
+
+    writeln!(svg, r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="white"/>"#, min.x, min.y, size.x, size.y).unwrap();
+
+    for (fixture_index, fixture) in fixtures.iter().enumerate() {
+        let pixel_radius = fixture.mapping.get_pitch().min_element() * fixture.transform.scale.abs().min_element() * 0.3;
+        let outline = fixture.world_outline();
+
+        writeln!(svg, "<g>").unwrap();
+
+        writeln!(svg, r#"<polygon points="{}" fill="none" stroke="black" stroke-width="1"/>"#, points(outline)).unwrap();
+
+        let wired_pixels: Vec<_> = (0..fixture.mapping.get_num_pixels())
+            .filter(|&i| fixture.mask.state(i) != PixelState::Skipped)
+            .collect();
+
+        writeln!(
+            svg,
+            r##"<polyline points="{}" fill="none" stroke="#1565c0" stroke-width="{:.2}" marker-mid="url(#arrow)" marker-end="url(#arrow)"/>"##,
+            points(wired_pixels.iter().map(|&i| fixture.world_pos(i))),
+            pixel_radius * 0.4
+        ).unwrap();
+
+        for &i in &wired_pixels {
+            let pos = fixture.world_pos(i);
+            let fill = match fixture.mask.state(i) {
+                PixelState::Dead => "red",
+                _ => "#9e9e9e",
+            };
+
+            writeln!(svg, r#"<circle cx="{:.1}" cy="{:.1}" r="{:.2}" fill="{fill}"/>"#, pos.x, pos.y, pixel_radius).unwrap();
+        }
+
+        if let Some(&first) = wired_pixels.first() {
+            let pos = fixture.world_pos(first);
+
+            writeln!(
+                svg,
+                r##"<circle cx="{x:.1}" cy="{y:.1}" r="{r:.2}" fill="#2e7d32"/><text x="{x:.1}" y="{y:.1}" font-size="{r:.2}" fill="white" text-anchor="middle" dominant-baseline="central">0</text>"##,
+                x = pos.x, y = pos.y, r = pixel_radius * 2.0
+            ).unwrap();
+        }
+
+        let label_pos = outline[0];
+        writeln!(
+            svg,
+            r#"<text x="{:.1}" y="{:.1}" font-size="10" fill="black">#{fixture_index} {} u: {} c: {}</text>"#,
+            label_pos.x, label_pos.y - 3.0, escape(&fixture.name), fixture.dmx_address.universe, fixture.dmx_address.channel
+        ).unwrap();
+
+        writeln!(svg, "</g>").unwrap();
+    }
+
+    svg.push_str("</svg>\n");
+
+    Ok(svg)
+}
+
+/// Rasterize a wiring diagram, text is drawn with the system fonts
+#[cfg(feature = "png")]
+pub fn svg_to_png(svg: &str) -> Result<Vec<u8>, String> {
+    use resvg::{tiny_skia, usvg::{self, fontdb, TreeParsing, TreePostProc}};
+
+    let mut tree = usvg::Tree::from_str(svg, &usvg::Options::default())
+        .map_err(|err| format!("The svg is invalid: {err}"))?;
+
+    let mut fontdb = fontdb::Database::new();
+    fontdb.load_system_fonts();
+
+    tree.postprocess(usvg::PostProcessingSteps { convert_text_into_paths: true }, &fontdb);
+
+    let size = tree.size.to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| format!("The diagram is too big to rasterize at {}x{}", size.width(), size.height()))?;
+
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    pixmap.encode_png().map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, wiring_svg};
+    use crate::{strip_mapping::StripMapping, LedMappingInfo};
+
+    #[test]
+    fn diagram() {
+        assert!(wiring_svg(&[]).is_err());
+
+        let strip = LedMappingInfo::new(StripMapping::new(4, false).into(), Default::default(), (0, 1).into())
+            .with_name("jaw");
+        let svg = wiring_svg(&[strip]).unwrap();
+
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        assert!(!svg.contains("inf") && !svg.contains("NaN"));
+        assert!(svg.contains("#0 jaw u: 1 c: 0"));
+        // every pixel and the start marker
+        assert_eq!(svg.matches("<circle").count(), 5);
+
+        assert_eq!(escape(r#"Tree & "Star" <it's>"#), "Tree &amp; &quot;Star&quot; &lt;it&apos;s&gt;");
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{mapping::LedMappingTrait, LedMappingInfo};
+
+/// Which fixtures get temporal dithering
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DitherSettings {
+    /// Used by fixtures that aren't listed, off unless turned on in the config
+    pub enabled: bool,
+    /// Fixture name or group to whether it is dithered
+    pub fixtures: HashMap<String, bool>,
+}
+
+impl DitherSettings {
+    pub fn enabled_for(&self, fixture: &LedMappingInfo) -> bool {
+        std::iter::once(&fixture.name)
+            .chain(&fixture.groups)
+            .find_map(|key| self.fixtures.get(key).copied())
+            .unwrap_or(self.enabled)
+    }
+}
+
+/// Quantizes output levels to 8 bit, carrying the rounding error of each channel over to the next frame.
+/// Levels between two steps then flicker between them with the right average,
+/// which hides the stepping of slow fades at low brightness.
+pub struct TemporalDither {
+    /// Per fixture, per pixel
+    errors: Vec<Vec<[f32; 3]>>,
+}
+
+impl TemporalDither {
+    pub fn new(fixtures: &[LedMappingInfo]) -> Self {
+        Self {
+            errors: fixtures.iter()
+                .map(|fixture| vec![[0.0; 3]; fixture.mapping.get_num_pixels()])
+                .collect(),
+        }
+    }
+
+    /// Levels in the range 0..1 to dmx values
+    pub fn quantize(&mut self, fixture_index: usize, enabled: bool, output: &[[f32; 3]]) -> Vec<[u8; 3]> {
+        let errors = &mut self.errors[fixture_index];
+
+        output.iter()
+            .zip(errors.iter_mut())
+            .map(|(levels, errors)| {
+                std::array::from_fn(|channel| {
+                    let target = levels[channel].clamp(0.0, 1.0) * 255.0;
+
+                    if !enabled {
+                        errors[channel] = 0.0;
+                        return target.round() as u8;
+                    }
+
+                    let value = (target + errors[channel]).round().clamp(0.0, 255.0);
+                    // the error stays within half a step, unless clamping at the ends
+                    errors[channel] = (target + errors[channel] - value).clamp(-0.5, 0.5);
+
+                    value as u8
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TemporalDither;
+    use crate::{strip_mapping::StripMapping, LedMappingInfo};
+
+    #[test]
+    fn average() {
+        let fixtures = vec![LedMappingInfo::new(StripMapping::new(1, false).into(), Default::default(), (0, 0).into())];
+        let mut dither = TemporalDither::new(&fixtures);
+
+        // a quarter of the way between 10 and 11
+        let level = 10.25 / 255.0;
+        let frames: Vec<u8> = (0..8)
+            .map(|_| dither.quantize(0, true, &[[level; 3]])[0][0])
+            .collect();
+
+        assert!(frames.iter().all(|&value| value == 10 || value == 11));
+        assert_eq!(frames.iter().map(|&value| value as u32).sum::<u32>(), 82);
+
+        assert_eq!(dither.quantize(0, false, &[[level; 3]]), vec![[10; 3]]);
+    }
+}
@@ -1,10 +1,12 @@
-use std::{time::Duration, f32::consts::{*}, num};
+use std::{f32::consts::{*}, num};
 
-use ecolor::{Hsva, Color32, Rgba, HsvaGamma};
-use glam::Vec2;
+use ecolor::{Hsva, Color32, Rgba};
+use glam::{Vec2, Vec3};
 use noise::{NoiseFn, Perlin};
 use palette::{rgb::Rgb, FromColor, Hsv, Srgb, LinSrgb, IntoColor};
 
+use crate::{feedback::CanvasBuffer, palettes::Palette};
+
 pub fn tri(pos: Vec2) -> f32 {
     // let length = pos.length();
 
@@ -31,12 +33,51 @@ impl Limitable for f32 {
 }
 
 pub struct DrawContext<'a> {
-    pub elapsed: Duration,
     pub elapsed_seconds: f32,
     pub audio: &'a [f32],
+    pub noise: &'a Perlin,
+    /// Lowest and highest corner of the area the fixtures cover in canvas space
+    pub bounds: (Vec2, Vec2),
+    /// What was drawn in the last frame, for effects that feed back on themselves
+    pub previous_frame: &'a CanvasBuffer,
+    /// Effects pick their hues from this, so the whole show can be re-themed
+    pub palette: &'a Palette,
+}
+
+/// The time of an effect that runs at its own speed. It moves on by the time since the last
+/// frame times the speed, so changing the speed doesn't make the animation jump.
+#[derive(Debug, Clone, Default)]
+pub struct Phase {
+    last_update: Option<f32>,
+    /// In seconds at the effect's speed
+    pub seconds: f32,
+}
+
+impl Phase {
+    /// Called once per frame, time can't run backwards
+    pub fn advance(&mut self, elapsed_seconds: f32, speed: f32) {
+        let delta = self.last_update.map_or(0.0, |last_update| (elapsed_seconds - last_update).max(0.0));
+        self.last_update = Some(elapsed_seconds);
+
+        self.seconds += delta * speed.max(0.0);
+    }
 }
 
 impl DrawContext<'_> {
+    /// The same context at the time of an effect's phase
+    pub fn at_phase(&self, phase: &Phase) -> Self {
+        DrawContext {
+            elapsed_seconds: phase.seconds,
+            ..*self
+        }
+    }
+
+    /// 3d perlin noise in the range -1..1
+    fn sample_noise_3d(&self, pos: Vec3) -> f32 {
+        let pos = pos.as_dvec3();
+        self.noise.get([pos.x, pos.y, pos.z]) as f32
+    }
+
     fn sample_audio(&self, pos: Vec2) -> f32 {
         if !self.audio.is_empty() {
             let audio_scale = Vec2::new(1.0, 1.0) /(16.0*4.0);
@@ -52,6 +93,26 @@ impl DrawContext<'_> {
     }
 }
 
+/// A context for tests at `seconds`, covering (-10, -10) to (10, 10) without audio, an empty
+/// previous frame and the default palette. Change the rest with `..test_context(seconds)`.
+#[cfg(test)]
+pub fn test_context(seconds: f32) -> DrawContext<'static> {
+    use std::sync::LazyLock;
+
+    static NOISE: LazyLock<Perlin> = LazyLock::new(Perlin::default);
+    static PREVIOUS_FRAME: LazyLock<CanvasBuffer> = LazyLock::new(CanvasBuffer::default);
+    static PALETTE: LazyLock<Palette> = LazyLock::new(Palette::default);
+
+    DrawContext {
+        elapsed_seconds: seconds,
+        audio: &[],
+        noise: &NOISE,
+        bounds: (Vec2::splat(-10.0), Vec2::splat(10.0)),
+        previous_frame: &PREVIOUS_FRAME,
+        palette: &PALETTE,
+    }
+}
+
 pub fn draw_blobs(ctx: &DrawContext, pos: Vec2) -> Rgba {
     let audio_val = ctx.sample_audio(pos).clamp(-1.0, 1.0);
 
@@ -103,12 +164,12 @@ pub fn draw_blobs(ctx: &DrawContext, pos: Vec2) -> Rgba {
 
     // let val = audio_val;
 
-    let hsv = HsvaGamma {
-        h: ((shape_val * 20.0 + -ctx.elapsed_seconds * 3.1232).sin()) * 0.2 + 0.8,
-        s: 1.0,
-        v: val,
-        a: 1.0
-    };
+    let color = ctx.palette.shade(
+        ((shape_val * 20.0 + -ctx.elapsed_seconds * 3.1232).sin()) * 0.2 + 0.8,
+        1.0,
+        val,
+        1.0
+    );
 
     // let hsv = HsvaGamma::new(
     //     ,
@@ -117,7 +178,7 @@ pub fn draw_blobs(ctx: &DrawContext, pos: Vec2) -> Rgba {
     //     1.0
     // );
 
-    hsv.into()
+    color
 }
 
 pub fn draw_lightning(ctx: &DrawContext, pos: Vec2) -> Rgba {
@@ -158,14 +219,60 @@ pub fn draw_lightning(ctx: &DrawContext, pos: Vec2) -> Rgba {
     
     let fade_out = (1.0-scaled_pos.length()).max(0.0).powf(0.5);
 
-    let hsv = HsvaGamma {
-        h: 0.6 + fine_detail_pre_abs % 0.2,
-        s: (pos_length).max(0.0).powf(0.5),
-        v: 1.0,
-        a: radial_line * fade_out
-    };
-
-    hsv.into()
+    ctx.palette.shade(
+        0.6 + fine_detail_pre_abs % 0.2,
+        (pos_length).max(0.0).powf(0.5),
+        1.0,
+        radial_line * fade_out
+    )
     
     // Rgba::WHITE * radial_line * fade_out
-}
\ No newline at end of file
+}
+
+/// Slow volumetric glow that drifts through the head in 3d
+pub fn draw_noise_glow(ctx: &DrawContext, pos: Vec3) -> Rgba {
+    let drift = Vec3::new(0.0, -0.3, 0.2) * ctx.elapsed_seconds;
+    let noise_val = ctx.sample_noise_3d(pos / 12.0 + drift);
+
+    ctx.palette.shade(
+        0.7 + noise_val * 0.1,
+        1.0,
+        (noise_val * 0.5 + 0.5).powi(4),
+        0.15
+    )
+}
+
+/// Classic demoscene plasma, overlapping sine waves cycling through the hues
+pub fn draw_plasma(ctx: &DrawContext, pos: Vec2) -> Rgba {
+    let t = ctx.elapsed_seconds;
+
+    let waves = (pos.x + t).sin()
+        + ((pos.y + t) * 0.5).sin()
+        + ((pos.x + pos.y + t) * 0.5).sin()
+        + ((pos + Vec2::new((t * 0.3).sin(), (t * 0.5).cos()) * 5.0).length() + t).sin();
+
+    ctx.palette.shade(
+        (waves / 8.0 + 0.5 + t * 0.05).fract(),
+        1.0,
+        (waves * 0.25 * PI).sin() * 0.25 + 0.75,
+        1.0
+    )
+}
+
+/// Perlin noise warped by itself, so it looks like it's flowing along a field
+pub fn draw_flow(ctx: &DrawContext, pos: Vec2) -> Rgba {
+    let t = ctx.elapsed_seconds * 0.2;
+
+    let field = Vec2::new(
+        ctx.sample_noise_3d(pos.extend(t)),
+        ctx.sample_noise_3d((pos + Vec2::new(5.2, 1.3)).extend(t)),
+    );
+    let noise_val = ctx.sample_noise_3d((pos + field * 4.0).extend(t * 0.5));
+
+    ctx.palette.shade(
+        (0.55 + noise_val * 0.3 + field.x * 0.1).rem_euclid(1.0),
+        1.0 - field.length().min(1.0) * 0.5,
+        (noise_val * 0.5 + 0.5).powi(2),
+        1.0
+    )
+}
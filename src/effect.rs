@@ -0,0 +1,282 @@
+use std::fmt::Display;
+
+use ecolor::Rgba;
+use enum_dispatch::enum_dispatch;
+use glam::{Vec2, Vec3};
+use serde::Deserialize;
+
+use crate::{draw::{draw_blobs, draw_flow, draw_lightning, draw_noise_glow, draw_plasma, DrawContext, Phase}, mapping::LedIndex, expression::ExpressionEffect, feedback::{Blur, Echo, Trails}, image_effect::ImageEffect, mouth::MouthEffect, particles::Particles, script::ScriptEffect, simulation::{Fire, Life, Rain}, text::TextEffect, video::VIDEO_EXTENSIONS, LedMappingInfo};
+
+/// A pixel to draw, positioned in canvas space
+pub struct Pixel {
+    pub index: LedIndex,
+    /// Position on the flat layout
+    pub pos: Vec2,
+    /// Position on the surface the fixture is mounted on
+    pub pos_3d: Vec3,
+}
+
+#[enum_dispatch(EffectEnum)]
+/// Something that draws onto the fixtures
+pub trait Effect {
+    /// Name the effect is registered under
+    fn name(&self) -> &str;
+
+    /// Called once per frame before anything is drawn
+    fn update(&mut self, _ctx: &DrawContext) {}
+
+    /// Color of a single pixel
+    fn render_pixel(&self, ctx: &DrawContext, pixel: &Pixel) -> Rgba;
+
+    /// Add the effect onto a whole fixture, `out` is indexed by pixel index.
+    /// Override this for effects that need more than one pixel at a time.
+    fn render_fixture(&self, ctx: &DrawContext, _fixture: &LedMappingInfo, pixels: &[Pixel], out: &mut [Rgba]) {
+        for pixel in pixels {
+            out[pixel.index] = out[pixel.index] + self.render_pixel(ctx, pixel);
+        }
+    }
+
+    /// Returns false if the effect has no parameter with that name
+    fn set_param(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
+
+    /// Like `set_param`, for parameters that pick one of a few named options.
+    /// Returns false if there is no such parameter or option.
+    fn set_text_param(&mut self, _name: &str, _value: &str) -> bool {
+        false
+    }
+}
+
+/// The value of an effect parameter, most of them are numbers
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Number(f32),
+    Text(String),
+}
+
+impl ParamValue {
+    /// Numbers if they parse as one, text otherwise
+    pub fn parse(value: &str) -> Self {
+        value.parse().map_or_else(|_| ParamValue::Text(value.to_owned()), ParamValue::Number)
+    }
+
+    /// Returns false if the effect has no parameter with that name taking this value
+    pub fn apply(&self, effect: &mut impl Effect, name: &str) -> bool {
+        match self {
+            ParamValue::Number(value) => effect.set_param(name, *value),
+            ParamValue::Text(value) => effect.set_text_param(name, value),
+        }
+    }
+}
+
+/// Draws a pixel from its position, at the phase of the pattern
+type DrawPattern = fn(&DrawContext, &Pixel, f32) -> Rgba;
+
+/// One of the built in patterns, animated at its own speed
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    name: &'static str,
+    /// Gets the scale, or 1 for patterns without one
+    draw: DrawPattern,
+    speed: f32,
+    phase: Phase,
+    brightness: f32,
+    /// Size of the pattern, bigger is finer. None if it can't be scaled.
+    scale: Option<f32>,
+}
+
+impl Pattern {
+    fn new(name: &'static str, draw: DrawPattern) -> Self {
+        Self { name, draw, speed: 1.0, phase: Phase::default(), brightness: 1.0, scale: None }
+    }
+
+    fn with_scale(self, scale: f32) -> Self {
+        Self { scale: Some(scale), ..self }
+    }
+}
+
+impl Effect for Pattern {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        self.phase.advance(ctx.elapsed_seconds, self.speed);
+    }
+
+    fn render_pixel(&self, ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        (self.draw)(&ctx.at_phase(&self.phase), pixel, self.scale.unwrap_or(1.0)) * self.brightness
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match (name, &mut self.scale) {
+            ("speed", _) => self.speed = value,
+            ("brightness", _) => self.brightness = value,
+            ("scale", Some(scale)) => *scale = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[enum_dispatch]
+#[derive(Debug, Clone)]
+pub enum EffectEnum {
+    Pattern,
+    Fire,
+    Life,
+    Rain,
+    ScriptEffect,
+    ExpressionEffect,
+    ImageEffect,
+    TextEffect,
+    MouthEffect,
+    Particles,
+    Trails,
+    Echo,
+    Blur,
+}
+
+/// Every effect that can be picked by name
+const REGISTRY: &[fn() -> EffectEnum] = &[
+    // the animated mouth shape
+    || Pattern::new("blobs", |ctx, pixel, _| draw_blobs(ctx, pixel.pos)).into(),
+    // radial flashes driven by the audio
+    || Pattern::new("lightning", |ctx, pixel, _| draw_lightning(ctx, pixel.pos)).into(),
+    // slow glow drifting through the head in 3d
+    || Pattern::new("noise_glow", |ctx, pixel, _| draw_noise_glow(ctx, pixel.pos_3d)).into(),
+    // overlapping sine waves in every color
+    || Pattern::new("plasma", |ctx, pixel, scale| draw_plasma(ctx, pixel.pos * scale)).with_scale(0.2).into(),
+    // noise flowing along a noise field
+    || Pattern::new("flow", |ctx, pixel, scale| draw_flow(ctx, pixel.pos * scale)).with_scale(0.05).into(),
+    || Fire::default().into(),
+    || Life::default().into(),
+    || Rain::default().into(),
+    || TextEffect::default().into(),
+    || MouthEffect::default().into(),
+    || Particles::default().into(),
+    || Trails::default().into(),
+    || Echo::default().into(),
+    || Blur::default().into(),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EffectError {
+    UnknownEffect(String),
+    UnknownParam { effect: String, param: String },
+    Expression(String),
+    Image(String),
+    Script(String),
+}
+
+impl Display for EffectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EffectError::UnknownEffect(name) => {
+                let names: Vec<_> = REGISTRY.iter().map(|create| create().name().to_owned()).collect();
+                write!(f, "There is no effect called {name:?}, expected one of {names:?}, a .rhai script, an image or a video")
+            },
+            EffectError::UnknownParam { effect, param } => write!(f, "The {effect} effect has no parameter {param:?}, or not with that value"),
+            EffectError::Expression(err) => write!(f, "Invalid expression: {err}"),
+            EffectError::Image(err) => write!(f, "Could not load the image {err}"),
+            EffectError::Script(err) => write!(f, "Could not load the script {err}"),
+        }
+    }
+}
+
+/// A color parameter like `#ff8800` or `ff8800`, in gamma space like the color pickers
+pub fn parse_color(hex: &str) -> Option<Rgba> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some(Rgba::from_srgba_unmultiplied(channel(0)?, channel(2)?, channel(4)?, 255))
+}
+
+fn is_media(name: &str) -> bool {
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+    ["png", "apng", "gif"].iter().chain(VIDEO_EXTENSIONS).any(|media| media.eq_ignore_ascii_case(extension))
+}
+
+/// Scripts, images and videos are named by the path of their file
+pub fn is_file_effect(name: &str) -> bool {
+    !name.starts_with("expr:") && (name.ends_with(".rhai") || is_media(name))
+}
+
+/// Create an effect with its default parameters.
+/// Names ending in .rhai are loaded as scripts, images and videos are played,
+/// and `expr:` is followed by an expression.
+pub fn create_effect(name: &str) -> Result<EffectEnum, EffectError> {
+    if let Some(source) = name.strip_prefix("expr:") {
+        return ExpressionEffect::compile(source)
+            .map(EffectEnum::from)
+            .map_err(EffectError::Expression);
+    }
+
+    if is_media(name) {
+        return ImageEffect::load(name.as_ref())
+            .map(EffectEnum::from)
+            .map_err(EffectError::Image);
+    }
+
+    if name.ends_with(".rhai") {
+        return ScriptEffect::load(name.into())
+            .map(EffectEnum::from)
+            .map_err(EffectError::Script);
+    }
+
+    REGISTRY.iter()
+        .map(|create| create())
+        .find(|effect| effect.name() == name)
+        .ok_or_else(|| EffectError::UnknownEffect(name.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Rgba;
+
+    use super::{create_effect, draw_blobs, parse_color, Effect, EffectError, Pattern};
+    use crate::draw::test_context;
+
+    #[test]
+    fn registry() {
+        let mut effect = create_effect("lightning").unwrap();
+
+        assert_eq!(effect.name(), "lightning");
+        assert!(effect.set_param("speed", 2.0));
+        assert!(!effect.set_param("size", 2.0));
+        assert!(!effect.set_param("scale", 2.0));
+        assert!(create_effect("plasma").unwrap().set_param("scale", 2.0));
+
+        assert_eq!(create_effect("sparkles").unwrap_err(), EffectError::UnknownEffect("sparkles".into()));
+    }
+
+    #[test]
+    fn speed_change() {
+        let mut blobs = Pattern::new("blobs", |ctx, pixel, _| draw_blobs(ctx, pixel.pos));
+        blobs.update(&test_context(10.0));
+        blobs.update(&test_context(11.0));
+        assert_eq!(blobs.phase.seconds, 1.0);
+
+        // twice as fast from here on, without jumping to twice the elapsed time
+        assert!(blobs.set_param("speed", 2.0));
+        blobs.update(&test_context(11.5));
+        assert_eq!(blobs.phase.seconds, 2.0);
+    }
+
+    #[test]
+    fn color_param() {
+        let orange = Rgba::from_srgba_unmultiplied(255, 136, 0, 255);
+        assert_eq!(parse_color("#ff8800"), Some(orange));
+        assert_eq!(parse_color("FF8800"), Some(orange));
+
+        assert_eq!(parse_color("#f80"), None);
+        assert_eq!(parse_color("#ff88zz"), None);
+        assert_eq!(parse_color("#ff8€0"), None);
+    }
+}
@@ -0,0 +1,547 @@
+use std::{f32::consts::{PI, TAU}, fmt::Debug, iter::Peekable, str::Chars};
+
+use ecolor::{linear_from_gamma, HsvaGamma, Rgba};
+use glam::{Vec2, Vec3};
+use noise::NoiseFn;
+
+use crate::{draw::DrawContext, effect::{Effect, Pixel}, feedback::CanvasBuffer, palettes::Palette};
+
+/// A value while evaluating an expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Float(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Color(Rgba),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Float(_) => "float",
+            Value::Vec2(_) => "vec2",
+            Value::Vec3(_) => "vec3",
+            Value::Color(_) => "color",
+        }
+    }
+
+    fn float(self) -> Result<f32, String> {
+        match self {
+            Value::Float(value) => Ok(value),
+            other => Err(format!("Expected a float, got a {}", other.type_name())),
+        }
+    }
+
+    /// Apply a function to every component, colors only scale as a whole
+    fn map(self, f: impl Fn(f32) -> f32) -> Result<Value, String> {
+        match self {
+            Value::Float(value) => Ok(Value::Float(f(value))),
+            Value::Vec2(value) => Ok(Value::Vec2(Vec2::new(f(value.x), f(value.y)))),
+            Value::Vec3(value) => Ok(Value::Vec3(Vec3::new(f(value.x), f(value.y), f(value.z)))),
+            Value::Color(_) => Err("Math functions don't work on colors".into()),
+        }
+    }
+
+    /// Combine two values component wise, a float is used for every component of a vector
+    fn zip(self, other: Value, f: impl Fn(f32, f32) -> f32) -> Result<Value, String> {
+        let zip2 = |a: Vec2, b: Vec2| Vec2::new(f(a.x, b.x), f(a.y, b.y));
+        let zip3 = |a: Vec3, b: Vec3| Vec3::new(f(a.x, b.x), f(a.y, b.y), f(a.z, b.z));
+
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(f(a, b))),
+            (Value::Vec2(a), Value::Vec2(b)) => Ok(Value::Vec2(zip2(a, b))),
+            (Value::Vec2(a), Value::Float(b)) => Ok(Value::Vec2(zip2(a, Vec2::splat(b)))),
+            (Value::Float(a), Value::Vec2(b)) => Ok(Value::Vec2(zip2(Vec2::splat(a), b))),
+            (Value::Vec3(a), Value::Vec3(b)) => Ok(Value::Vec3(zip3(a, b))),
+            (Value::Vec3(a), Value::Float(b)) => Ok(Value::Vec3(zip3(a, Vec3::splat(b)))),
+            (Value::Float(a), Value::Vec3(b)) => Ok(Value::Vec3(zip3(Vec3::splat(a), b))),
+            (a, b) => Err(format!("Can't combine a {} with a {}", a.type_name(), b.type_name())),
+        }
+    }
+
+    fn into_color(self) -> Result<Rgba, String> {
+        match self {
+            Value::Float(value) => Ok(Rgba::from_gray(value)),
+            Value::Vec3(value) => Ok(Rgba::from_rgb(value.x, value.y, value.z)),
+            Value::Color(color) => Ok(color),
+            Value::Vec2(_) => Err("The expression has to give a color, a float or a vec3, not a vec2".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+/// GLSL style modulo, the result has the sign of `b`
+fn modulo(a: f32, b: f32) -> f32 {
+    a - b * (a / b).floor()
+}
+
+impl BinaryOp {
+    fn apply(self, a: Value, b: Value) -> Result<Value, String> {
+        match (self, a, b) {
+            (BinaryOp::Add, Value::Color(a), Value::Color(b)) => Ok(Value::Color(a + b)),
+            (BinaryOp::Mul, Value::Color(a), Value::Float(b)) | (BinaryOp::Mul, Value::Float(b), Value::Color(a)) => Ok(Value::Color(a * b)),
+            (BinaryOp::Mul, Value::Color(a), Value::Color(b)) => Ok(Value::Color(a * b)),
+            (BinaryOp::Add, a, b) => a.zip(b, |a, b| a + b),
+            (BinaryOp::Sub, a, b) => a.zip(b, |a, b| a - b),
+            (BinaryOp::Mul, a, b) => a.zip(b, |a, b| a * b),
+            (BinaryOp::Div, a, b) => a.zip(b, |a, b| a / b),
+            (BinaryOp::Mod, a, b) => a.zip(b, modulo),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variable {
+    Pos,
+    Pos3d,
+    X,
+    Y,
+    Time,
+    Audio,
+    Pi,
+    Tau,
+}
+
+impl Variable {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "pos" => Variable::Pos,
+            "pos_3d" => Variable::Pos3d,
+            "x" => Variable::X,
+            "y" => Variable::Y,
+            "t" => Variable::Time,
+            "audio" => Variable::Audio,
+            "pi" => Variable::Pi,
+            "tau" => Variable::Tau,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Sin, Cos, Tan, Abs, Fract, Floor, Ceil, Sqrt, Exp, Sign,
+    Min, Max, Pow, Step, Mod, Atan,
+    Mix, Clamp, Smoothstep,
+    Length, Distance, Dot, Normalize,
+    Vec2, Vec3, Noise,
+    Hsv, Hsva, Rgb, Palette,
+}
+
+impl Function {
+    fn parse(name: &str) -> Option<Self> {
+        use Function::*;
+
+        Some(match name {
+            "sin" => Sin, "cos" => Cos, "tan" => Tan, "abs" => Abs, "fract" => Fract,
+            "floor" => Floor, "ceil" => Ceil, "sqrt" => Sqrt, "exp" => Exp, "sign" => Sign,
+            "min" => Min, "max" => Max, "pow" => Pow, "step" => Step, "mod" => Mod, "atan" => Atan,
+            "mix" => Mix, "clamp" => Clamp, "smoothstep" => Smoothstep,
+            "length" => Length, "distance" => Distance, "dot" => Dot, "normalize" => Normalize,
+            "vec2" => Vec2, "vec3" => Vec3, "noise" => Noise,
+            "hsv" => Hsv, "hsva" => Hsva, "rgb" => Rgb, "palette" => Palette,
+            _ => return None,
+        })
+    }
+
+    fn call(self, ctx: &DrawContext, args: &[Value]) -> Result<Value, String> {
+        use Function::*;
+
+        let vector_op = |a: Value, b: Value, f2: fn(glam::Vec2, glam::Vec2) -> f32, f3: fn(glam::Vec3, glam::Vec3) -> f32| match (a, b) {
+            (Value::Vec2(a), Value::Vec2(b)) => Ok(Value::Float(f2(a, b))),
+            (Value::Vec3(a), Value::Vec3(b)) => Ok(Value::Float(f3(a, b))),
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(f2(glam::Vec2::new(a, 0.0), glam::Vec2::new(b, 0.0)))),
+            (a, b) => Err(format!("Expected two vectors of the same size, got a {} and a {}", a.type_name(), b.type_name())),
+        };
+
+        match (self, args) {
+            (Sin, [a]) => a.map(f32::sin),
+            (Cos, [a]) => a.map(f32::cos),
+            (Tan, [a]) => a.map(f32::tan),
+            (Abs, [a]) => a.map(f32::abs),
+            (Fract, [a]) => a.map(|a| a - a.floor()),
+            (Floor, [a]) => a.map(f32::floor),
+            (Ceil, [a]) => a.map(f32::ceil),
+            (Sqrt, [a]) => a.map(f32::sqrt),
+            (Exp, [a]) => a.map(f32::exp),
+            (Sign, [a]) => a.map(f32::signum),
+
+            (Min, [a, b]) => a.zip(*b, f32::min),
+            (Max, [a, b]) => a.zip(*b, f32::max),
+            (Pow, [a, b]) => a.zip(*b, f32::powf),
+            (Step, [edge, a]) => edge.zip(*a, |edge, a| if a < edge { 0.0 } else { 1.0 }),
+            (Mod, [a, b]) => a.zip(*b, modulo),
+            (Atan, [y, x]) => y.zip(*x, f32::atan2),
+
+            (Mix, [Value::Color(a), Value::Color(b), t]) => {
+                let t = t.float()?;
+                Ok(Value::Color(*a * (1.0 - t) + *b * t))
+            },
+            (Mix, [a, b, t]) => {
+                let difference = b.zip(*a, |b, a| b - a)?;
+                a.zip(difference.zip(*t, |difference, t| difference * t)?, |a, step| a + step)
+            },
+            (Clamp, [a, low, high]) => a.zip(*low, f32::max)?.zip(*high, f32::min),
+            (Smoothstep, [low, high, a]) => {
+                let (low, high) = (low.float()?, high.float()?);
+
+                a.map(|a| {
+                    let t = ((a - low) / (high - low)).clamp(0.0, 1.0);
+                    t * t * (3.0 - 2.0 * t)
+                })
+            },
+
+            (Length, [Value::Float(a)]) => Ok(Value::Float(a.abs())),
+            (Length, [Value::Vec2(a)]) => Ok(Value::Float(a.length())),
+            (Length, [Value::Vec3(a)]) => Ok(Value::Float(a.length())),
+            (Distance, [a, b]) => vector_op(*a, *b, glam::Vec2::distance, glam::Vec3::distance),
+            (Dot, [a, b]) => vector_op(*a, *b, glam::Vec2::dot, glam::Vec3::dot),
+            (Normalize, [Value::Vec2(a)]) => Ok(Value::Vec2(a.normalize_or_zero())),
+            (Normalize, [Value::Vec3(a)]) => Ok(Value::Vec3(a.normalize_or_zero())),
+
+            (Vec2, [x, y]) => Ok(Value::Vec2(glam::Vec2::new(x.float()?, y.float()?))),
+            (Vec3, [x, y, z]) => Ok(Value::Vec3(glam::Vec3::new(x.float()?, y.float()?, z.float()?))),
+            (Vec3, [Value::Vec2(xy), z]) => Ok(Value::Vec3(xy.extend(z.float()?))),
+            (Noise, [Value::Vec2(pos)]) => Ok(Value::Float(ctx.noise.get(pos.as_dvec2().to_array()) as f32)),
+            (Noise, [Value::Vec3(pos)]) => Ok(Value::Float(ctx.noise.get(pos.as_dvec3().to_array()) as f32)),
+
+            (Hsv, [h, s, v]) => Ok(Value::Color(HsvaGamma { h: h.float()?, s: s.float()?, v: v.float()?, a: 1.0 }.into())),
+            (Hsva, [h, s, v, a]) => Ok(Value::Color(HsvaGamma { h: h.float()?, s: s.float()?, v: v.float()?, a: a.float()? }.into())),
+            // the hue wheel unless another palette is picked
+            (Palette, [t]) => Ok(Value::Color(ctx.palette.shade(t.float()?, 1.0, 1.0, 1.0))),
+            (Palette, [t, v]) => Ok(Value::Color(ctx.palette.shade(t.float()?, 1.0, v.float()?, 1.0))),
+            // in gamma space like the color pickers
+            (Rgb, [r, g, b]) => Ok(Value::Color(Rgba::from_rgb(
+                linear_from_gamma(r.float()?),
+                linear_from_gamma(g.float()?),
+                linear_from_gamma(b.float()?),
+            ))),
+
+            (function, args) => {
+                let types: Vec<_> = args.iter().map(Value::type_name).collect();
+                Err(format!("{function:?} doesn't take {types:?}").to_lowercase())
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f32),
+    Variable(Variable),
+    Negate(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+    /// Swizzle like `.x` or `.yx`, as component indices
+    Field(Box<Node>, Vec<usize>),
+}
+
+/// Everything an expression can read for one pixel
+struct Env<'a, 'b> {
+    ctx: &'a DrawContext<'b>,
+    pos: Vec2,
+    pos_3d: Vec3,
+}
+
+impl Node {
+    fn eval(&self, env: &Env) -> Result<Value, String> {
+        match self {
+            Node::Number(value) => Ok(Value::Float(*value)),
+            Node::Variable(variable) => Ok(match variable {
+                Variable::Pos => Value::Vec2(env.pos),
+                Variable::Pos3d => Value::Vec3(env.pos_3d),
+                Variable::X => Value::Float(env.pos.x),
+                Variable::Y => Value::Float(env.pos.y),
+                Variable::Time => Value::Float(env.ctx.elapsed_seconds),
+                Variable::Audio => Value::Float(env.ctx.audio.first().copied().unwrap_or(0.0)),
+                Variable::Pi => Value::Float(PI),
+                Variable::Tau => Value::Float(TAU),
+            }),
+            Node::Negate(node) => match node.eval(env)? {
+                Value::Color(color) => Ok(Value::Color(color * -1.0)),
+                value => value.map(|value| -value),
+            },
+            Node::Binary(op, a, b) => op.apply(a.eval(env)?, b.eval(env)?),
+            Node::Call(function, args) => {
+                let args = args.iter().map(|arg| arg.eval(env)).collect::<Result<Vec<_>, _>>()?;
+                function.call(env.ctx, &args)
+            },
+            Node::Field(node, components) => {
+                let value = node.eval(env)?;
+
+                let source: Vec<f32> = match value {
+                    Value::Vec2(value) => value.to_array().to_vec(),
+                    Value::Vec3(value) => value.to_array().to_vec(),
+                    other => return Err(format!("A {} has no fields", other.type_name())),
+                };
+
+                let picked = components.iter()
+                    .map(|&i| source.get(i).copied().ok_or_else(|| format!("A {} has no field {}", value.type_name(), ['x', 'y', 'z'][i])))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(match picked[..] {
+                    [x] => Value::Float(x),
+                    [x, y] => Value::Vec2(Vec2::new(x, y)),
+                    [x, y, z] => Value::Vec3(Vec3::new(x, y, z)),
+                    _ => unreachable!("swizzles are 1 to 3 components long"),
+                })
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Symbol(char),
+}
+
+/// Whether a '.' is the start of a number like `.5`, rather than a field access
+fn starts_fraction(chars: &Peekable<Chars>) -> bool {
+    let mut ahead = chars.clone();
+    ahead.next();
+    ahead.peek().is_some_and(char::is_ascii_digit)
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars: Peekable<Chars> = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => { chars.next(); },
+            _ if c.is_ascii_digit() || (c == '.' && starts_fraction(&chars)) => {
+                let mut number = String::new();
+
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                    number.push(c);
+                    chars.next();
+                }
+
+                tokens.push(Token::Number(number.parse().map_err(|_| format!("Invalid number {number:?}"))?));
+            },
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+
+                while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                    ident.push(c);
+                    chars.next();
+                }
+
+                tokens.push(Token::Ident(ident));
+            },
+            '+' | '-' | '*' | '/' | '%' | '(' | ')' | ',' | '.' => {
+                tokens.push(Token::Symbol(c));
+                chars.next();
+            },
+            _ => return Err(format!("Unexpected {c:?}")),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, from the lowest precedence up
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn eat(&mut self, symbol: char) -> bool {
+        self.tokens.next_if_eq(&Token::Symbol(symbol)).is_some()
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("Expected {symbol:?}, found {:?}", self.tokens.peek()))
+        }
+    }
+
+    fn sum(&mut self) -> Result<Node, String> {
+        let mut node = self.product()?;
+
+        loop {
+            let op = if self.eat('+') {
+                BinaryOp::Add
+            } else if self.eat('-') {
+                BinaryOp::Sub
+            } else {
+                return Ok(node);
+            };
+
+            node = Node::Binary(op, Box::new(node), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+
+        loop {
+            let op = if self.eat('*') {
+                BinaryOp::Mul
+            } else if self.eat('/') {
+                BinaryOp::Div
+            } else if self.eat('%') {
+                BinaryOp::Mod
+            } else {
+                return Ok(node);
+            };
+
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.eat('-') {
+            return Ok(Node::Negate(Box::new(self.unary()?)));
+        }
+
+        let mut node = self.primary()?;
+
+        while self.eat('.') {
+            let Some(Token::Ident(fields)) = self.tokens.next() else {
+                return Err("Expected a field name after '.'".into());
+            };
+
+            let components = fields.chars()
+                .map(|field| "xyz".find(field).ok_or_else(|| format!("Unknown field {field:?}")))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if components.len() > 3 {
+                return Err(format!("Too many fields in .{fields}"));
+            }
+
+            node = Node::Field(Box::new(node), components);
+        }
+
+        Ok(node)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.tokens.next() {
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Symbol('(')) => {
+                let node = self.sum()?;
+                self.expect(')')?;
+                Ok(node)
+            },
+            Some(Token::Ident(name)) if self.eat('(') => {
+                let function = Function::parse(&name).ok_or_else(|| format!("Unknown function {name:?}"))?;
+                let mut args = vec![];
+
+                if !self.eat(')') {
+                    loop {
+                        args.push(self.sum()?);
+
+                        if self.eat(')') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+
+                Ok(Node::Call(function, args))
+            },
+            Some(Token::Ident(name)) => Variable::parse(&name)
+                .map(Node::Variable)
+                .ok_or_else(|| format!("Unknown variable {name:?}, expected pos, pos_3d, x, y, t, audio, pi or tau")),
+            other => Err(format!("Unexpected {other:?}")),
+        }
+    }
+}
+
+/// A one line pattern formula, like `hsv(fract(length(pos) * 0.1 - t), 1, 0.5)`.
+/// It's parsed and type checked once, then evaluated for every pixel.
+#[derive(Clone, PartialEq)]
+pub struct ExpressionEffect {
+    source: String,
+    root: Node,
+}
+
+impl Debug for ExpressionEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ExpressionEffect({:?})", self.source)
+    }
+}
+
+impl ExpressionEffect {
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let mut parser = Parser { tokens: tokenize(source)?.into_iter().peekable() };
+
+        let root = parser.sum()?;
+
+        if let Some(token) = parser.tokens.next() {
+            return Err(format!("Unexpected {token:?} after the end of the expression"));
+        }
+
+        // there are no branches, so evaluating once checks the types of every node
+        let noise = noise::Perlin::default();
+        let previous_frame = CanvasBuffer::default();
+        let palette = Palette::default();
+        let ctx = DrawContext { elapsed_seconds: 0.0, audio: &[], noise: &noise, bounds: (Vec2::ZERO, Vec2::ONE), previous_frame: &previous_frame, palette: &palette };
+        root.eval(&Env { ctx: &ctx, pos: Vec2::ZERO, pos_3d: Vec3::ZERO })?.into_color()?;
+
+        Ok(Self { source: source.to_owned(), root })
+    }
+}
+
+impl Effect for ExpressionEffect {
+    fn name(&self) -> &str {
+        "expr"
+    }
+
+    fn render_pixel(&self, ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        self.root.eval(&Env { ctx, pos: pixel.pos, pos_3d: pixel.pos_3d })
+            .and_then(Value::into_color)
+            .unwrap_or(Rgba::TRANSPARENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec3};
+
+    use super::ExpressionEffect;
+    use crate::{draw::{test_context, DrawContext}, effect::{Effect, Pixel}};
+
+    #[test]
+    fn evaluate() {
+        let ctx = DrawContext { audio: &[0.5], bounds: (Vec2::ZERO, Vec2::ONE), ..test_context(2.0) };
+        let pixel = Pixel { index: 0, pos: Vec2::new(3.0, 4.0), pos_3d: Vec3::ZERO };
+
+        let render = |source| {
+            let color = ExpressionEffect::compile(source).unwrap().render_pixel(&ctx, &pixel);
+            [color.r(), color.g(), color.b()]
+        };
+
+        assert_eq!(render("length(pos) / 10"), [0.5; 3]);
+        assert_eq!(render("fract(t * 0.625 + -pos.y * .5)"), [0.25; 3]);
+        assert_eq!(render("vec3(pos.yx * 0.125, audio)"), [0.5, 0.375, 0.5]);
+        assert_eq!(render("mix(vec3(0, 0, 1), vec3(1, 0, 0), 0.25)"), [0.25, 0.0, 0.75]);
+        assert_eq!(render("rgb(1, 1, 1) * max(step(5, x), 0.5) + rgb(0, 0, 0)"), [0.5; 3]);
+        assert_eq!(render("(pos * 2).x % 4"), [2.0; 3]);
+        assert_eq!(render("palette(0.5)"), [0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(ExpressionEffect::compile("sin(pos").is_err());
+        assert!(ExpressionEffect::compile("pos").is_err());
+        assert!(ExpressionEffect::compile("sin(1, 2)").is_err());
+        assert!(ExpressionEffect::compile("hsv(pos, 1, 1)").is_err());
+        assert!(ExpressionEffect::compile("speed * 2").is_err());
+        assert!(ExpressionEffect::compile("pos.w").is_err());
+        assert!(ExpressionEffect::compile("x y").is_err());
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::VecDeque;
+
+use ecolor::Rgba;
+use glam::{IVec2, UVec2, Vec2};
+
+use crate::{draw::DrawContext, effect::{Effect, Pixel}};
+
+/// Echoes further back than this aren't kept, every frame in between is held in memory
+const MAX_ECHO_DELAY: f32 = 2.0;
+
+/// A rendered frame in canvas space, with one cell per canvas unit around the fixture pixels
+#[derive(Debug, Clone, Default)]
+pub struct CanvasBuffer {
+    origin: Vec2,
+    size: UVec2,
+    /// Sum of the colors drawn into each cell and how many there were
+    cells: Vec<(Rgba, f32)>,
+}
+
+impl CanvasBuffer {
+    /// An empty frame covering the lowest to the highest corner
+    pub fn new(bounds: (Vec2, Vec2)) -> Self {
+        let (min, max) = bounds;
+        let size = (max - min).max(Vec2::ZERO).ceil().as_uvec2() + 1;
+
+        Self {
+            origin: min,
+            size,
+            cells: vec![(Rgba::TRANSPARENT, 0.0); (size.x * size.y) as usize],
+        }
+    }
+
+    /// Empties every cell, so the buffer can be drawn into again
+    pub fn clear(&mut self) {
+        self.cells.fill((Rgba::TRANSPARENT, 0.0));
+    }
+
+    /// Like `clone_from`, but keeps the memory of the cells
+    fn copy_from(&mut self, other: &Self) {
+        self.origin = other.origin;
+        self.size = other.size;
+        self.cells.clone_from(&other.cells);
+    }
+
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        if cell.x < 0 || cell.y < 0 || cell.x >= self.size.x as i32 || cell.y >= self.size.y as i32 {
+            return None;
+        }
+
+        Some((cell.y * self.size.x as i32 + cell.x) as usize)
+    }
+
+    /// Average color drawn into a cell, None if no pixel is in it
+    fn get(&self, cell: IVec2) -> Option<Rgba> {
+        let (sum, count) = self.cells[self.index(cell)?];
+        (count > 0.0).then(|| sum * (1.0 / count))
+    }
+
+    /// Draws the color of a pixel into the cell it's closest to
+    pub fn add(&mut self, pos: Vec2, color: Rgba) {
+        if let Some(i) = self.index((pos - self.origin).round().as_ivec2()) {
+            let (sum, count) = &mut self.cells[i];
+            *sum = *sum + color;
+            *count += 1.0;
+        }
+    }
+
+    /// The color at a position, blended between the closest cells that have pixels in them.
+    /// Far from any pixel it's transparent.
+    pub fn sample(&self, pos: Vec2) -> Rgba {
+        let local = pos - self.origin;
+        let base = local.floor();
+        let (t, base) = (local - base, base.as_ivec2());
+
+        let corners = [
+            (IVec2::ZERO, (1.0 - t.x) * (1.0 - t.y)),
+            (IVec2::X, t.x * (1.0 - t.y)),
+            (IVec2::Y, (1.0 - t.x) * t.y),
+            (IVec2::ONE, t.x * t.y),
+        ];
+
+        let (sum, weight) = corners.iter()
+            .filter_map(|&(offset, weight)| Some((self.get(base + offset)? * weight, weight)))
+            .fold((Rgba::TRANSPARENT, 0.0), |(sum, total), (color, weight)| (sum + color, total + weight));
+
+        if weight > 0.0 { sum * (1.0 / weight) } else { Rgba::TRANSPARENT }
+    }
+
+    /// Every cell with pixels averaged with the cells around it up to `radius` away, written
+    /// into `out`
+    fn blur_into(&self, radius: i32, out: &mut Self) {
+        let cells = (0..self.cells.len())
+            .map(|i| {
+                let cell = IVec2::new(i as i32 % self.size.x as i32, i as i32 / self.size.x as i32);
+
+                if self.get(cell).is_none() {
+                    return (Rgba::TRANSPARENT, 0.0);
+                }
+
+                (-radius..=radius).flat_map(|y| (-radius..=radius).map(move |x| IVec2::new(x, y)))
+                    .filter_map(|offset| self.get(cell + offset))
+                    .fold((Rgba::TRANSPARENT, 0.0), |(sum, count), color| (sum + color, count + 1.0))
+            });
+
+        out.origin = self.origin;
+        out.size = self.size;
+        out.cells.clear();
+        out.cells.extend(cells);
+    }
+}
+
+/// How much is left after `delta` seconds of something that fades to 5% in `fade` seconds
+fn fade_factor(fade: f32, delta: f32) -> f32 {
+    if fade > 0.0 { 0.05f32.powf(delta / fade) } else { 0.0 }
+}
+
+/// The last frame fading out, for trails and motion blur. Zooming and rotating it turns
+/// it into a feedback tunnel.
+#[derive(Debug, Clone)]
+pub struct Trails {
+    /// Seconds until the trail has faded to 5%
+    fade: f32,
+    /// How much bigger the trail gets per second
+    zoom: f32,
+    /// In degrees per second
+    rotation: f32,
+    /// Moves the center of the zoom away from the center of the canvas, in canvas units
+    offset: Vec2,
+    brightness: f32,
+    last_update: Option<f32>,
+    /// Change since the last frame
+    factor: f32,
+    scale: f32,
+    angle: f32,
+    center: Vec2,
+}
+
+impl Default for Trails {
+    fn default() -> Self {
+        Self {
+            fade: 0.5,
+            zoom: 1.0,
+            rotation: 0.0,
+            offset: Vec2::ZERO,
+            brightness: 1.0,
+            last_update: None,
+            factor: 0.0,
+            scale: 1.0,
+            angle: 0.0,
+            center: Vec2::ZERO,
+        }
+    }
+}
+
+impl Effect for Trails {
+    fn name(&self) -> &str {
+        "trails"
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        let delta = self.last_update.map_or(0.0, |last_update| (ctx.elapsed_seconds - last_update).max(0.0));
+        self.last_update = Some(ctx.elapsed_seconds);
+
+        self.factor = fade_factor(self.fade, delta);
+        self.scale = self.zoom.max(f32::EPSILON).powf(delta);
+        self.angle = self.rotation.to_radians() * delta;
+
+        let (min, max) = ctx.bounds;
+        self.center = (min + max) / 2.0 + self.offset;
+    }
+
+    fn render_pixel(&self, ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        // where this pixel was before the last frame was zoomed and rotated
+        let relative = Vec2::from_angle(-self.angle).rotate(pixel.pos - self.center) / self.scale;
+
+        ctx.previous_frame.sample(self.center + relative) * (self.factor * self.brightness)
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "fade" => self.fade = value,
+            "zoom" => self.zoom = value,
+            "rotation" => self.rotation = value,
+            "x" => self.offset.x = value,
+            "y" => self.offset.y = value,
+            "brightness" => self.brightness = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// The frame from a while ago, which echoes itself as well
+#[derive(Debug, Clone)]
+pub struct Echo {
+    /// In seconds
+    delay: f32,
+    /// Brightness of the echo
+    amount: f32,
+    /// Past frames and when they were drawn, oldest first
+    history: VecDeque<(f32, CanvasBuffer)>,
+    /// The last frame that dropped out of the history, reused for the next one
+    spare: Option<CanvasBuffer>,
+}
+
+impl Default for Echo {
+    fn default() -> Self {
+        Self { delay: 0.5, amount: 0.6, history: VecDeque::new(), spare: None }
+    }
+}
+
+impl Effect for Echo {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        let now = ctx.elapsed_seconds;
+
+        if self.history.back().is_some_and(|(time, _)| *time > now) {
+            self.history.clear();
+        }
+
+        let mut frame = self.spare.take().unwrap_or_default();
+        frame.copy_from(ctx.previous_frame);
+        self.history.push_back((now, frame));
+
+        // keep the newest frame that's old enough, and everything after it
+        let delay = self.delay.clamp(0.0, MAX_ECHO_DELAY);
+        while self.history.get(1).is_some_and(|(time, _)| now - time >= delay) {
+            self.spare = self.history.pop_front().map(|(_, frame)| frame);
+        }
+    }
+
+    fn render_pixel(&self, ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        match self.history.front() {
+            Some((time, frame)) if ctx.elapsed_seconds - time >= self.delay.clamp(0.0, MAX_ECHO_DELAY) => frame.sample(pixel.pos) * self.amount,
+            _ => Rgba::TRANSPARENT,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "delay" => self.delay = value,
+            "amount" | "brightness" => self.amount = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// The last frame blurred, spreading light out like it's diffusing
+#[derive(Debug, Clone)]
+pub struct Blur {
+    /// In canvas units
+    radius: f32,
+    /// Seconds until the blur has faded to 5%
+    fade: f32,
+    brightness: f32,
+    blurred: CanvasBuffer,
+    last_update: Option<f32>,
+    factor: f32,
+}
+
+impl Default for Blur {
+    fn default() -> Self {
+        Self {
+            radius: 1.0,
+            fade: 0.5,
+            brightness: 1.0,
+            blurred: CanvasBuffer::default(),
+            last_update: None,
+            factor: 0.0,
+        }
+    }
+}
+
+impl Effect for Blur {
+    fn name(&self) -> &str {
+        "blur"
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        let delta = self.last_update.map_or(0.0, |last_update| (ctx.elapsed_seconds - last_update).max(0.0));
+        self.last_update = Some(ctx.elapsed_seconds);
+
+        self.factor = fade_factor(self.fade, delta);
+        ctx.previous_frame.blur_into(self.radius.round().max(0.0) as i32, &mut self.blurred);
+    }
+
+    fn render_pixel(&self, _ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        self.blurred.sample(pixel.pos) * (self.factor * self.brightness)
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "radius" => self.radius = value,
+            "fade" => self.fade = value,
+            "brightness" => self.brightness = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Rgba;
+    use glam::{Vec2, Vec3};
+
+    use super::{CanvasBuffer, Echo, Trails};
+    use crate::{draw::{test_context, DrawContext}, effect::{Effect, Pixel}};
+
+    #[test]
+    fn feedback() {
+        let bounds = (Vec2::ZERO, Vec2::new(4.0, 1.0));
+        let mut frame = CanvasBuffer::new(bounds);
+        frame.add(Vec2::new(1.0, 0.0), Rgba::WHITE);
+        frame.add(Vec2::new(2.0, 0.0), Rgba::BLACK);
+
+        // exact on the pixels, blended in between, and nothing far from them
+        assert_eq!(frame.sample(Vec2::new(1.0, 0.0)), Rgba::WHITE);
+        assert_eq!(frame.sample(Vec2::new(1.5, 0.0)).r(), 0.5);
+        assert_eq!(frame.sample(Vec2::new(1.0, 0.5)), Rgba::WHITE);
+        assert_eq!(frame.sample(Vec2::new(4.0, 1.0)), Rgba::TRANSPARENT);
+
+        let mut blurred = CanvasBuffer::default();
+        frame.blur_into(1, &mut blurred);
+        assert_eq!(blurred.sample(Vec2::new(1.0, 0.0)).r(), 0.5);
+
+        let ctx = |seconds| DrawContext { bounds, previous_frame: &frame, ..test_context(seconds) };
+        let pixel = Pixel { index: 0, pos: Vec2::new(1.0, 0.0), pos_3d: Vec3::ZERO };
+
+        // faded to 5% after `fade` seconds
+        let mut trails = Trails::default();
+        trails.update(&ctx(0.0));
+        trails.update(&ctx(0.5));
+        assert!((trails.render_pixel(&ctx(0.5), &pixel).r() - 0.05).abs() < 0.001);
+
+        // twice as big after a second, so pixels show what was halfway to the center
+        assert!(trails.set_param("zoom", 2.0));
+        trails.update(&ctx(1.5));
+        assert!((trails.render_pixel(&ctx(1.5), &pixel).r() - 0.5 * 0.0025).abs() < 0.0001);
+
+        // the frame only shows up once it's old enough
+        let mut echo = Echo::default();
+        echo.update(&ctx(0.0));
+        assert_eq!(echo.render_pixel(&ctx(0.0), &pixel), Rgba::TRANSPARENT);
+        echo.update(&ctx(0.3));
+        echo.update(&ctx(0.6));
+        assert_eq!(echo.history.len(), 3);
+        assert_eq!(echo.render_pixel(&ctx(0.6), &pixel), Rgba::WHITE * 0.6);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+use crate::{draw::DrawContext, effect::{create_effect, is_file_effect, Effect, EffectEnum, EffectError, ParamValue}, layer::{BlendMode, EffectStack, Layer}, LedMappingInfo};
+
+/// Picks the effects for each fixture from the groups it's tagged with
+pub struct GroupEffects {
+    default: EffectStack,
+    /// Checked in order, the first group a fixture is in wins
+    groups: Vec<(String, EffectStack)>,
+}
+
+impl GroupEffects {
+    pub fn new(default: EffectStack) -> Self {
+        Self { default, groups: vec![] }
+    }
+
+    pub fn with_group(mut self, group: impl Into<String>, stack: EffectStack) -> Self {
+        self.groups.push((group.into(), stack));
+        self
+    }
+
+    pub fn stack_for(&self, fixture: &LedMappingInfo) -> &EffectStack {
+        self.groups.iter()
+            .find(|(group, _)| fixture.groups.contains(group))
+            .map(|(_, stack)| stack)
+            .unwrap_or(&self.default)
+    }
+
+    /// Replace the stack of fixtures that aren't in any group
+    pub fn set_default(&mut self, stack: EffectStack) {
+        self.default = stack;
+    }
+
+    /// Every effect, including the masks
+    fn effects_mut(&mut self) -> impl Iterator<Item=&mut EffectEnum> {
+        self.default.iter_mut()
+            .chain(self.groups.iter_mut().flat_map(|(_, stack)| stack.iter_mut()))
+            .flat_map(|layer| std::iter::once(&mut layer.effect).chain(&mut layer.mask))
+    }
+
+    pub fn update(&mut self, ctx: &DrawContext) {
+        for effect in self.effects_mut() {
+            effect.update(ctx);
+        }
+    }
+
+    /// Set a parameter of every effect with that name, returns false if none have it
+    pub fn set_param(&mut self, effect_name: &str, param: &str, value: &ParamValue) -> bool {
+        let mut found = false;
+
+        for effect in self.effects_mut().filter(|effect| effect.name() == effect_name) {
+            found |= value.apply(effect, param);
+        }
+
+        found
+    }
+}
+
+/// An effect by name, optionally with parameters
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum EffectSpec {
+    Name(String),
+    WithParams {
+        effect: String,
+        #[serde(flatten)]
+        params: HashMap<String, ParamValue>,
+    },
+}
+
+fn create_with_params(name: &str, params: &HashMap<String, ParamValue>) -> Result<EffectEnum, EffectError> {
+    let mut effect = create_effect(name)?;
+
+    for (param, value) in params {
+        if !value.apply(&mut effect, param) {
+            return Err(EffectError::UnknownParam { effect: name.to_owned(), param: param.clone() });
+        }
+    }
+
+    Ok(effect)
+}
+
+impl EffectSpec {
+    pub fn create(&self) -> Result<EffectEnum, EffectError> {
+        match self {
+            EffectSpec::Name(name) => create_effect(name),
+            EffectSpec::WithParams { effect, params } => create_with_params(effect, params),
+        }
+    }
+
+    fn name_mut(&mut self) -> &mut String {
+        match self {
+            EffectSpec::Name(name) | EffectSpec::WithParams { effect: name, .. } => name,
+        }
+    }
+}
+
+fn full_opacity() -> f32 {
+    1.0
+}
+
+/// A layer by effect name, or a table with the blending and effect parameters
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum LayerSpec {
+    Name(String),
+    Configured {
+        effect: String,
+        #[serde(default = "full_opacity")]
+        opacity: f32,
+        #[serde(default)]
+        blend: BlendMode,
+        mask: Option<EffectSpec>,
+        #[serde(flatten)]
+        params: HashMap<String, ParamValue>,
+    },
+}
+
+impl LayerSpec {
+    /// The names of the effect and its mask
+    fn names_mut(&mut self) -> impl Iterator<Item=&mut String> {
+        let (effect, mask) = match self {
+            LayerSpec::Name(name) => (name, None),
+            LayerSpec::Configured { effect, mask, .. } => (effect, mask.as_mut()),
+        };
+
+        std::iter::once(effect).chain(mask.map(EffectSpec::name_mut))
+    }
+
+    pub fn create(&self) -> Result<Layer, EffectError> {
+        match self {
+            LayerSpec::Name(name) => create_effect(name).map(Layer::from),
+            LayerSpec::Configured { effect, opacity, blend, mask, params } => Ok(Layer {
+                effect: create_with_params(effect, params)?,
+                opacity: *opacity,
+                blend: *blend,
+                mask: mask.as_ref().map(EffectSpec::create).transpose()?,
+            }),
+        }
+    }
+}
+
+fn create_stack(specs: &[LayerSpec]) -> Result<EffectStack, EffectError> {
+    specs.iter().map(LayerSpec::create).collect()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupStackConfig {
+    group: String,
+    stack: Vec<LayerSpec>,
+}
+
+/// Effect stacks from the config, replacing the built in ones
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EffectsConfig {
+    default: Vec<LayerSpec>,
+    /// Checked in order, the first group a fixture is in wins
+    #[serde(default)]
+    groups: Vec<GroupStackConfig>,
+}
+
+impl EffectsConfig {
+    fn names_mut(&mut self) -> impl Iterator<Item=&mut String> {
+        self.default.iter_mut()
+            .chain(self.groups.iter_mut().flat_map(|group| group.stack.iter_mut()))
+            .flat_map(LayerSpec::names_mut)
+    }
+
+    /// Makes the paths of scripts, images and videos relative to `dir`, and checks that
+    /// they exist
+    pub fn resolve_paths(&mut self, dir: &Path) -> Result<(), String> {
+        for name in self.names_mut().filter(|name| is_file_effect(name)) {
+            let path = dir.join(&*name);
+
+            if !path.is_file() {
+                return Err(format!("The effect file {path:?} does not exist"));
+            }
+
+            *name = path.to_string_lossy().into_owned();
+        }
+
+        Ok(())
+    }
+
+    pub fn build(&self) -> Result<GroupEffects, EffectError> {
+        self.groups.iter()
+            .try_fold(GroupEffects::new(create_stack(&self.default)?), |effects, GroupStackConfig { group, stack }| {
+                Ok(effects.with_group(group, create_stack(stack)?))
+            })
+    }
+}
+
+/// Turn the names of a control message into a stack
+pub fn stack_from_names(names: &[String]) -> Result<EffectStack, EffectError> {
+    names.iter().map(|name| create_effect(name).map(Layer::from)).collect()
+}
@@ -0,0 +1,421 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+use ecolor::Rgba;
+use glam::{UVec2, Vec2};
+use image::{codecs::{gif::GifDecoder, png::PngDecoder}, AnimationDecoder, DynamicImage, Frame, ImageDecoder, ImageResult, RgbaImage};
+
+use crate::{draw::DrawContext, effect::{Effect, Pixel}, video};
+
+/// Shorter frame delays are taken as missing, like browsers do
+const MIN_FRAME_DELAY: f32 = 0.02;
+/// Frames with a missing delay are played like browsers do, at 10 frames per second
+const DEFAULT_FRAME_DELAY: f32 = 0.1;
+
+/// Playback starts over when the effect wasn't drawn for this many seconds,
+/// so animations start from the beginning whenever their scene is shown
+const RESTART_AFTER: f32 = 1.0;
+
+/// How the image is sized to the area the fixtures cover
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fit {
+    /// The whole image is visible, with empty space on two sides if the aspect ratio differs
+    #[default]
+    Contain,
+    /// The whole area is covered, cropping the image if the aspect ratio differs
+    Cover,
+    /// The image is stretched to the area
+    Stretch,
+}
+
+impl Fit {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "contain" => Some(Fit::Contain),
+            "cover" => Some(Fit::Cover),
+            "stretch" => Some(Fit::Stretch),
+            _ => None,
+        }
+    }
+}
+
+/// How the image is sampled between its pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    /// Hard pixel edges, for pixel art
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+impl Filter {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "nearest" => Some(Filter::Nearest),
+            "bilinear" => Some(Filter::Bilinear),
+            _ => None,
+        }
+    }
+}
+
+/// What happens at the end of an animation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Playback {
+    #[default]
+    Loop,
+    /// Stay on the last frame, for intros
+    Hold,
+}
+
+impl Playback {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "loop" => Some(Playback::Loop),
+            "hold" => Some(Playback::Hold),
+            _ => None,
+        }
+    }
+}
+
+/// One frame of the animation, in linear premultiplied colors row by row
+#[derive(Debug)]
+pub struct ImageFrame {
+    pub pixels: Vec<Rgba>,
+    /// Seconds since the start of the animation
+    pub start: f32,
+}
+
+/// Scaled down like videos are
+fn convert_frame(image: &RgbaImage, start: f32) -> ImageFrame {
+    let pixels = image.pixels()
+        .map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            Rgba::from_srgba_unmultiplied(r, g, b, a)
+        })
+        .collect();
+
+    let size = UVec2::from(image.dimensions());
+    let (factor, small_size) = video::scaled_size(size);
+
+    ImageFrame { pixels: video::downsample(pixels, size, factor, small_size), start }
+}
+
+/// Converts animation frames, which are already composited onto the full image of `size`,
+/// until the end or until they take up `max_memory`
+fn convert_animation(frames: impl IntoIterator<Item=ImageResult<Frame>>, size: UVec2, max_memory: usize) -> ImageResult<(UVec2, Vec<ImageFrame>, f32)> {
+    let (_, small_size) = video::scaled_size(size);
+    let mut converted = vec![];
+    let mut start = 0.0;
+
+    for frame in frames.into_iter().take(video::max_frames(small_size, max_memory)) {
+        let frame = frame?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay = numer as f32 / denom as f32 / 1000.0;
+
+        converted.push(convert_frame(frame.buffer(), start));
+        start += if delay < MIN_FRAME_DELAY { DEFAULT_FRAME_DELAY } else { delay };
+    }
+
+    Ok((small_size, converted, start))
+}
+
+/// Decodes a PNG, APNG or GIF into frames with their start times, and the length of a loop
+pub fn decode(path: &Path) -> Result<(UVec2, Vec<ImageFrame>, f32), String> {
+    let decode = || -> ImageResult<_> {
+        let file = BufReader::new(File::open(path)?);
+
+        let is_gif = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+
+        if is_gif {
+            let decoder = GifDecoder::new(file)?;
+            let size = decoder.dimensions().into();
+            return convert_animation(decoder.into_frames(), size, video::MAX_MEMORY);
+        }
+
+        let decoder = PngDecoder::new(file)?;
+        let size = decoder.dimensions().into();
+
+        if decoder.is_apng() {
+            convert_animation(decoder.apng().into_frames(), size, video::MAX_MEMORY)
+        } else {
+            let frame = convert_frame(&DynamicImage::from_decoder(decoder)?.to_rgba8(), 0.0);
+            Ok((video::scaled_size(size).1, vec![frame], 0.0))
+        }
+    };
+
+    decode().map_err(|err| err.to_string())
+}
+
+/// A still image, animation or video, stretched over the canvas.
+/// Plays PNG, APNG and GIF files with their own frame timing, and videos.
+#[derive(Debug, Clone)]
+pub struct ImageEffect {
+    name: String,
+    size: UVec2,
+    frames: Arc<Vec<ImageFrame>>,
+    /// Length of one loop in seconds
+    duration: f32,
+    /// A file that is still being decoded, nothing is drawn until it's done
+    loading: Option<video::PendingClip>,
+    /// The frame to draw, picked in update
+    frame: usize,
+    playback: Playback,
+    /// Seconds into the animation at its speed
+    time: f32,
+    last_update: Option<f32>,
+    fit: Fit,
+    filter: Filter,
+    /// Multiplies the size picked by the fit mode
+    scale: f32,
+    /// Moves the image away from the center of the canvas, in canvas units
+    offset: Vec2,
+    speed: f32,
+    brightness: f32,
+}
+
+impl ImageEffect {
+    /// Images and videos are told apart by their extension and decoded in the background,
+    /// errors contain the path
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let name = path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        // missing files are still reported straight away
+        File::open(path).map_err(|err| format!("{path:?}: {err}"))?;
+
+        Ok(Self {
+            loading: Some(video::load(path)),
+            ..Self::from_frames(name, UVec2::ONE, vec![], 0.0)
+        })
+    }
+
+    /// `duration` is the length of one loop in seconds
+    pub fn from_frames(name: String, size: UVec2, frames: Vec<ImageFrame>, duration: f32) -> Self {
+        Self {
+            name,
+            size,
+            frames: Arc::new(frames),
+            duration,
+            loading: None,
+            frame: 0,
+            playback: Playback::default(),
+            time: 0.0,
+            last_update: None,
+            fit: Fit::default(),
+            filter: Filter::default(),
+            scale: 1.0,
+            offset: Vec2::ZERO,
+            speed: 1.0,
+            brightness: 1.0,
+        }
+    }
+
+    /// Position on the image in pixels, with the pixel centers at .5
+    fn image_pos(&self, ctx: &DrawContext, pos: Vec2) -> Vec2 {
+        let (min, max) = ctx.bounds;
+        let area = (max - min).max(Vec2::splat(f32::EPSILON));
+        let size = self.size.as_vec2();
+
+        // canvas units per image pixel
+        let fitted = area / size;
+        let units_per_pixel = match self.fit {
+            Fit::Contain => Vec2::splat(fitted.min_element()),
+            Fit::Cover => Vec2::splat(fitted.max_element()),
+            Fit::Stretch => fitted,
+        } * self.scale;
+
+        let center = (min + max) / 2.0 + self.offset;
+
+        (pos - center) / units_per_pixel + size / 2.0
+    }
+
+    /// Transparent outside of the image
+    fn texel(&self, frame: &ImageFrame, x: i32, y: i32) -> Rgba {
+        if x < 0 || y < 0 || x >= self.size.x as i32 || y >= self.size.y as i32 {
+            return Rgba::TRANSPARENT;
+        }
+
+        frame.pixels[y as usize * self.size.x as usize + x as usize]
+    }
+
+    fn sample(&self, image_pos: Vec2) -> Rgba {
+        let frame = &self.frames[self.frame];
+
+        match self.filter {
+            Filter::Nearest => {
+                let texel = image_pos.floor();
+                self.texel(frame, texel.x as i32, texel.y as i32)
+            },
+            Filter::Bilinear => {
+                if image_pos.cmplt(Vec2::ZERO).any() || image_pos.cmpge(self.size.as_vec2()).any() {
+                    return Rgba::TRANSPARENT;
+                }
+
+                // clamped to the edge pixels, so the edges don't fade out
+                let last = self.size.as_ivec2() - 1;
+                let texel_pos = (image_pos - 0.5).clamp(Vec2::ZERO, last.as_vec2());
+                let [x, y] = texel_pos.as_ivec2().to_array();
+                let [x1, y1] = [(x + 1).min(last.x), (y + 1).min(last.y)];
+                let t = texel_pos.fract();
+
+                let mix = |a: Rgba, b: Rgba, t: f32| a * (1.0 - t) + b * t;
+                let top = mix(self.texel(frame, x, y), self.texel(frame, x1, y), t.x);
+                let bottom = mix(self.texel(frame, x, y1), self.texel(frame, x1, y1), t.x);
+
+                mix(top, bottom, t.y)
+            },
+        }
+    }
+}
+
+impl Effect for ImageEffect {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        if let Some(clip) = self.loading.as_ref().and_then(|loading| loading.get()) {
+            match clip {
+                Ok(clip) => {
+                    self.size = clip.size;
+                    self.frames = clip.frames.clone();
+                    self.duration = clip.duration;
+                },
+                Err(err) => eprintln!("Could not play {:?}: {err}", self.name),
+            }
+            self.loading = None;
+        }
+
+        // moved on by the time since the last frame, so changing the speed doesn't jump
+        match self.last_update {
+            Some(last_update) if ctx.elapsed_seconds - last_update <= RESTART_AFTER => {
+                self.time += (ctx.elapsed_seconds - last_update).max(0.0) * self.speed.max(0.0);
+            },
+            _ => self.time = 0.0,
+        }
+        self.last_update = Some(ctx.elapsed_seconds);
+
+        if self.duration <= 0.0 {
+            return;
+        }
+
+        let time = match self.playback {
+            Playback::Loop => self.time % self.duration,
+            Playback::Hold => self.time,
+        };
+        self.frame = self.frames.partition_point(|frame| frame.start <= time).saturating_sub(1);
+    }
+
+    fn render_pixel(&self, ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        if self.frames.is_empty() {
+            return Rgba::TRANSPARENT;
+        }
+
+        self.sample(self.image_pos(ctx, pixel.pos)) * self.brightness
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "scale" => self.scale = value,
+            "x" => self.offset.x = value,
+            "y" => self.offset.y = value,
+            "speed" => self.speed = value,
+            "brightness" => self.brightness = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_text_param(&mut self, name: &str, value: &str) -> bool {
+        match (name, Fit::parse(value), Filter::parse(value), Playback::parse(value)) {
+            ("fit", Some(fit), _, _) => self.fit = fit,
+            ("filter", _, Some(filter), _) => self.filter = filter,
+            ("playback", _, _, Some(playback)) => self.playback = playback,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Rgba;
+    use glam::{Vec2, Vec3};
+    use image::{Delay, Frame, RgbaImage};
+
+    use super::{convert_animation, ImageEffect};
+    use crate::{draw::test_context as ctx, effect::{Effect, Pixel}};
+
+    #[test]
+    fn playback() {
+        // two frames of 2x1 pixels, black and white on the left then the other way around
+        let frame = |left: u8, delay_ms| {
+            let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { [left, left, left, 255].into() } else { [255 - left, 255 - left, 255 - left, 255].into() });
+            Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1))
+        };
+
+        let (size, frames, duration) = convert_animation([Ok(frame(0, 500)), Ok(frame(255, 0))], [2, 1].into(), 1024).unwrap();
+        assert_eq!((size, duration), ([2, 1].into(), 0.6));
+
+        let mut effect = ImageEffect::from_frames("test".into(), [2, 1].into(), frames, duration);
+        assert!(effect.set_text_param("filter", "nearest"));
+        assert!(!effect.set_text_param("fit", "nearest"));
+
+        let pixel = |x| Pixel { index: 0, pos: Vec2::new(x, 0.0), pos_3d: Vec3::ZERO };
+
+        let left_at = |effect: &mut ImageEffect, seconds| {
+            effect.update(&ctx(seconds));
+            effect.render_pixel(&ctx(seconds), &pixel(-5.0))
+        };
+
+        // contained in the square canvas, 10 units per pixel
+        assert_eq!(left_at(&mut effect, 10.0), Rgba::BLACK);
+        assert_eq!(effect.render_pixel(&ctx(10.0), &pixel(5.0)), Rgba::WHITE);
+        assert_eq!(effect.render_pixel(&ctx(10.0), &pixel(0.0)).a(), 1.0);
+
+        assert_eq!(left_at(&mut effect, 10.55), Rgba::WHITE);
+        assert_eq!(left_at(&mut effect, 10.7), Rgba::BLACK);
+
+        assert!(effect.set_text_param("playback", "hold"));
+        assert_eq!(left_at(&mut effect, 11.5), Rgba::WHITE);
+
+        // shown again after a while
+        assert_eq!(left_at(&mut effect, 20.0), Rgba::BLACK);
+    }
+
+    #[test]
+    fn large_animation() {
+        let frame = || Ok(Frame::from_parts(RgbaImage::from_pixel(480, 270, [255, 255, 255, 255].into()), 0, 0, Delay::from_numer_denom_ms(40, 1)));
+
+        // scaled down like videos, and cut off once the frames fill the memory
+        let memory = 10 * 96 * 54 * size_of::<Rgba>();
+        let (size, frames, duration) = convert_animation(std::iter::repeat_with(frame).take(200), [480, 270].into(), memory).unwrap();
+
+        assert_eq!(size, [96, 54].into());
+        assert_eq!(frames[0].pixels.len(), 96 * 54);
+        assert_eq!(frames.len(), 10);
+        assert!((duration - 0.4).abs() < 0.001);
+    }
+
+    #[test]
+    fn load_in_background() {
+        let path = std::env::temp_dir().join("rpi_led_matrix_test_logo.png");
+        RgbaImage::from_pixel(3, 2, [255, 255, 255, 255].into()).save(&path).unwrap();
+
+        // nothing is drawn until the worker is done
+        let mut effect = ImageEffect::load(&path).unwrap();
+        let pixel = Pixel { index: 0, pos: Vec2::ZERO, pos_3d: Vec3::ZERO };
+        assert_eq!(effect.render_pixel(&ctx(0.0), &pixel), Rgba::TRANSPARENT);
+
+        while effect.loading.as_ref().is_some_and(|loading| loading.get().is_none()) {
+            std::thread::yield_now();
+        }
+
+        effect.update(&ctx(0.0));
+        assert_eq!((effect.size, effect.frames.len()), ([3, 2].into(), 1));
+        assert_eq!(effect.render_pixel(&ctx(0.0), &pixel), Rgba::WHITE);
+
+        std::fs::remove_file(&path).ok();
+        assert!(ImageEffect::load(&path).is_err());
+    }
+}
@@ -0,0 +1,442 @@
+use std::{fmt::Display, path::Path};
+
+use glam::{Affine2, Vec2};
+
+use crate::{
+    custom_mapping::CustomMapping,
+    mapping::{DmxAddress, LedIndex, LedMappingEnum, LedMappingTrait, UPos, CHANNELS_PER_UNIVERSE},
+    matrix_mapping::DEFAULT_MATRIX_PITCH,
+    strip_mapping::StripMapping,
+    transform::Transform2D,
+    LedMappingInfo,
+};
+
+/// xLights has no physical units, so one xLights unit is treated as one matrix pixel
+pub const XLIGHTS_UNIT_SIZE: f32 = DEFAULT_MATRIX_PITCH;
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(std::io::Error),
+    Xml(roxmltree::Error),
+    /// The file was readable but the layout in it didn't make sense
+    Format(String),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Io(err) => write!(f, "Could not read the layout file: {err}"),
+            ImportError::Xml(err) => write!(f, "Invalid xml: {err}"),
+            ImportError::Format(msg) => write!(f, "Invalid layout: {msg}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<roxmltree::Error> for ImportError {
+    fn from(value: roxmltree::Error) -> Self {
+        Self::Xml(value)
+    }
+}
+
+fn format_err<T>(msg: impl Into<String>) -> Result<T, ImportError> {
+    Err(ImportError::Format(msg.into()))
+}
+
+/// Load fixtures from an xLights layout (.xml, .xmodel) or a custom grid text file
+pub fn load_layout(path: &Path) -> Result<Vec<LedMappingInfo>, ImportError> {
+    let text = std::fs::read_to_string(path)?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("xml" | "xmodel") => parse_xlights(&text, XLIGHTS_UNIT_SIZE),
+        _ => parse_grid_layout(&text),
+    }
+}
+
+/// Turn 0 based absolute dmx channels into a DmxAddress, None past the last universe
+fn absolute_dmx_address(channel: usize) -> Option<DmxAddress> {
+    let universe = u8::try_from(channel / CHANNELS_PER_UNIVERSE).ok()?;
+    Some((channel % CHANNELS_PER_UNIVERSE, universe).into())
+}
+
+fn is_empty_cell(cell: &str) -> bool {
+    matches!(cell.trim(), "" | "." | "-")
+}
+
+/// Merge the layers of a 3d xLights custom model cell by cell, into rows of cells.
+/// Every cell can only hold a node in one of the layers.
+fn merge_layers(custom_model: &str) -> Result<Vec<Vec<&str>>, ImportError> {
+    let mut rows: Vec<Vec<&str>> = vec![];
+
+    for (z, layer) in custom_model.split('|').enumerate() {
+        for (y, row) in layer.split(';').enumerate() {
+            for (x, cell) in row.split(',').enumerate() {
+                if rows.len() <= y {
+                    rows.resize(y + 1, vec![]);
+                }
+                if rows[y].len() <= x {
+                    rows[y].resize(x + 1, "");
+                }
+
+                if is_empty_cell(cell) {
+                    continue;
+                }
+
+                if !is_empty_cell(rows[y][x]) {
+                    return format_err(format!("layer {z} row {y} column {x} has node {} but another layer already has node {}", cell.trim(), rows[y][x].trim()));
+                }
+
+                rows[y][x] = cell;
+            }
+        }
+    }
+
+    Ok(rows)
+}
+
+/// Collect grid cells into positions in node order.
+/// Cells hold 1 based node numbers, empty cells have no pixel.
+/// If a node shows up in multiple cells, the first one is used.
+fn grid_positions<'a>(rows: impl IntoIterator<Item=impl IntoIterator<Item=&'a str>>) -> Result<Vec<UPos>, ImportError> {
+    let mut positions: Vec<Option<UPos>> = vec![];
+
+    for (y, row) in rows.into_iter().enumerate() {
+        for (x, cell) in row.into_iter().enumerate() {
+            let cell = cell.trim();
+
+            if is_empty_cell(cell) {
+                continue;
+            }
+
+            let node: usize = match cell.parse() {
+                Ok(node) if node > 0 => node,
+                _ => return format_err(format!("'{cell}' at row {y} column {x} is not a node number")),
+            };
+
+            if positions.len() < node {
+                positions.resize(node, None);
+            }
+
+            positions[node-1].get_or_insert(UPos::new(x as u32, y as u32));
+        }
+    }
+
+    positions.into_iter()
+        .enumerate()
+        .map(|(i, pos)| pos.ok_or_else(|| ImportError::Format(format!("node {} is missing from the grid", i+1))))
+        .collect()
+}
+
+/// Parse all the custom and single line models in an xLights rgbeffects file or .xmodel export.
+/// `unit_size` is the size of an xLights unit (and a custom model cell) in mm.
+pub fn parse_xlights(xml: &str, unit_size: f32) -> Result<Vec<LedMappingInfo>, ImportError> {
+    let document = roxmltree::Document::parse(xml)?;
+
+    let mut fixtures = vec![];
+
+    for node in document.descendants() {
+        let display_as = match node.tag_name().name() {
+            "custommodel" => "Custom",
+            "model" => node.attribute("DisplayAs").unwrap_or_default(),
+            _ => continue,
+        };
+
+        let name = node.attribute("name").unwrap_or("unnamed");
+
+        let mapping: LedMappingEnum = match display_as {
+            "Custom" => {
+                let Some(custom_model) = node.attribute("CustomModel") else {
+                    return format_err(format!("custom model {name} has no CustomModel data"));
+                };
+
+                // 3d custom models have a layer per depth, they are flattened onto the same grid
+                CustomMapping::new(grid_positions(merge_layers(custom_model)?)?, unit_size).into()
+            },
+            "Single Line" => {
+                let strings = xlights_number(node, "parm1", 1.0)? as LedIndex;
+                let nodes = xlights_number(node, "parm2", 1.0)? as LedIndex;
+
+                StripMapping::new(strings*nodes, false)
+                    .with_pitch(unit_size)
+                    .into()
+            },
+            _ => {
+                eprintln!("Skipping xLights model {name}, {display_as:?} models are not supported");
+                continue;
+            },
+        };
+
+        let dmx_address = match node.attribute("StartChannel") {
+            Some(start_channel) => parse_start_channel(start_channel)
+                .ok_or_else(|| ImportError::Format(format!("model {name} has an unsupported start channel {start_channel:?}")))?,
+            None => (0, 0).into(),
+        };
+
+        // xLights positions models by their center with y pointing up
+        let center = Vec2::new(
+            xlights_number(node, "WorldPosX", 0.0)?,
+            -xlights_number(node, "WorldPosY", 0.0)?
+        ) * unit_size;
+        let scale = Vec2::new(xlights_number(node, "ScaleX", 1.0)?, xlights_number(node, "ScaleY", 1.0)?);
+        let rotation = -xlights_number(node, "RotateZ", 0.0)?.to_radians();
+
+        let half_size = mapping.get_size().as_vec2() * mapping.get_pitch() / 2.0;
+        let translation = center - Affine2::from_scale_angle_translation(scale, rotation, Vec2::ZERO).transform_vector2(half_size);
+
+        let transform = Transform2D { translation, rotation, scale };
+
+        fixtures.push(LedMappingInfo::new(mapping, transform, dmx_address).with_name(name));
+    }
+
+    Ok(fixtures)
+}
+
+fn xlights_number(node: roxmltree::Node, attribute: &str, default: f32) -> Result<f32, ImportError> {
+    match node.attribute(attribute) {
+        Some(value) => value.trim().parse()
+            .or_else(|_| format_err(format!("{attribute}={value:?} is not a number"))),
+        None => Ok(default),
+    }
+}
+
+/// xLights start channels are either absolute ("1234") or universe based ("#2:1"), both 1 based
+fn parse_start_channel(start_channel: &str) -> Option<DmxAddress> {
+    match start_channel.strip_prefix('#') {
+        Some(universe_channel) => {
+            let (universe, channel) = universe_channel.split_once(':')?;
+            let channel: usize = channel.parse().ok()?;
+
+            Some((channel.checked_sub(1)?, universe.parse().ok()?).into())
+        },
+        None => {
+            let channel: usize = start_channel.parse().ok()?;
+            absolute_dmx_address(channel.checked_sub(1)?)
+        }
+    }
+}
+
+/**
+ * Parse a custom grid layout, which looks like
+ * ```text
+ * # comment
+ * [fixture]
+ * name = mouth
+ * groups = mouth, front   # used to pick the effects
+ * universe = 44
+ * channel = 0     # 0 based
+ * x = -160        # mm, top left corner of the grid
+ * y = -80
+ * rotation = 90   # degrees
+ * pitch = 10      # mm between pixels
+ * dead = 3, 7     # node numbers of broken pixels
+ * 1, 2, 3, 4
+ * 8, 7,  , 5
+ * ```
+ * Grid rows hold 1 based node numbers, cells are split by commas or whitespace.
+ * Empty, "." or "-" cells have no pixel.
+ */
+pub fn parse_grid_layout(text: &str) -> Result<Vec<LedMappingInfo>, ImportError> {
+    struct GridFixture<'a> {
+        name: &'a str,
+        groups: Vec<&'a str>,
+        rows: Vec<Vec<&'a str>>,
+        address: DmxAddress,
+        translation: Vec2,
+        rotation: f32,
+        pitch: f32,
+        dead: Vec<LedIndex>,
+    }
+
+    let mut grids: Vec<GridFixture> = vec![];
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let line_number = line_number + 1;
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line == "[fixture]" {
+            grids.push(GridFixture {
+                name: "",
+                groups: vec![],
+                rows: vec![],
+                address: (0, 0).into(),
+                translation: Vec2::ZERO,
+                rotation: 0.0,
+                pitch: DEFAULT_MATRIX_PITCH,
+                dead: vec![],
+            });
+            continue;
+        }
+
+        let Some(grid) = grids.last_mut() else {
+            return format_err(format!("line {line_number} is outside of a [fixture]"));
+        };
+
+        match line.split_once('=') {
+            Some((key, value)) => {
+                let value = value.trim();
+                let number = || value.parse::<f32>()
+                    .or_else(|_| format_err(format!("{value:?} on line {line_number} is not a number")));
+                let whole_number = || value.parse::<usize>()
+                    .or_else(|_| format_err(format!("{value:?} on line {line_number} is not a whole number")));
+
+                match key.trim() {
+                    "name" => grid.name = value,
+                    "groups" => grid.groups.extend(value.split(',').map(str::trim)),
+                    "universe" => grid.address.universe = u8::try_from(whole_number()?)
+                        .or_else(|_| format_err(format!("universe {value} on line {line_number} is over 255")))?,
+                    "channel" => grid.address.channel = whole_number()?,
+                    "x" => grid.translation.x = number()?,
+                    "y" => grid.translation.y = number()?,
+                    "rotation" => grid.rotation = number()?.to_radians(),
+                    "pitch" => grid.pitch = number()?,
+                    "dead" => {
+                        for node in value.split(',') {
+                            match node.trim().parse::<LedIndex>() {
+                                Ok(node) if node > 0 => grid.dead.push(node - 1),
+                                _ => return format_err(format!("{node:?} on line {line_number} is not a node number")),
+                            }
+                        }
+                    },
+                    key => return format_err(format!("unknown key {key:?} on line {line_number}")),
+                }
+            },
+            None if line.contains(',') => grid.rows.push(line.split(',').collect()),
+            None => grid.rows.push(line.split_whitespace().collect()),
+        }
+    }
+
+    grids.into_iter()
+        .map(|grid| {
+            let positions = grid_positions(grid.rows)?;
+
+            if let Some(dead) = grid.dead.iter().find(|&&dead| positions.len() <= dead) {
+                return format_err(format!("dead node {} is not in the grid", dead+1));
+            }
+
+            let transform = Transform2D {
+                translation: grid.translation,
+                rotation: grid.rotation,
+                ..Default::default()
+            };
+
+            let mut fixture = LedMappingInfo::new(CustomMapping::new(positions, grid.pitch).into(), transform, grid.address)
+                .with_name(grid.name);
+            fixture.groups.extend(grid.groups.into_iter().map(String::from));
+            fixture.mask.dead.extend(grid.dead);
+
+            Ok(fixture)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::UVec2;
+
+    use super::{parse_grid_layout, parse_start_channel, parse_xlights, grid_positions, merge_layers};
+    use crate::mapping::LedMappingTrait;
+
+    #[test]
+    fn start_channel() {
+        assert_eq!(parse_start_channel("1"), Some((0, 0).into()));
+        assert_eq!(parse_start_channel("512"), Some((1, 1).into()));
+        assert_eq!(parse_start_channel("#2:4"), Some((3, 2).into()));
+        assert_eq!(parse_start_channel(">Mouth:1"), None);
+        assert_eq!(parse_start_channel("0"), None);
+        assert_eq!(parse_start_channel("200000"), None);
+        assert_eq!(parse_start_channel("#256:1"), None);
+    }
+
+    #[test]
+    fn grid() {
+        let positions = grid_positions([vec!["1", "", "2"], vec!["4", "3", "."]]).unwrap();
+        assert_eq!(positions, vec![UVec2::new(0, 0), UVec2::new(2, 0), UVec2::new(1, 1), UVec2::new(0, 1)]);
+
+        assert!(grid_positions([vec!["1", "3"]]).is_err());
+        assert!(grid_positions([vec!["1", "x"]]).is_err());
+    }
+
+    #[test]
+    fn layers() {
+        // two 3x2 layers, the second one filling the gaps of the first
+        let rows = merge_layers("1,,2;,,|,3,;4,,5").unwrap();
+        assert_eq!(rows, [vec!["1", "3", "2"], vec!["4", "", "5"]]);
+
+        let positions = grid_positions(rows).unwrap();
+        assert_eq!(positions[2], UVec2::new(1, 0));
+        assert_eq!(positions[4], UVec2::new(2, 1));
+
+        assert!(merge_layers("1,2|3,").is_err());
+    }
+
+    #[test]
+    fn xlights() {
+        let xml = r##"<xrgb><models>
+            <model name="Mouth" DisplayAs="Custom" StartChannel="#1:4" WorldPosX="10" WorldPosY="0"
+                CustomModel="1,,2;,3," parm1="3" parm2="2"/>
+            <model name="Jaw" DisplayAs="Single Line" StartChannel="31" parm1="1" parm2="20"/>
+            <model name="Tree" DisplayAs="Tree 360" StartChannel="91"/>
+        </models></xrgb>"##;
+
+        let fixtures = parse_xlights(xml, 10.0).unwrap();
+        assert_eq!(fixtures.len(), 2);
+
+        assert_eq!(fixtures[0].name, "Mouth");
+        assert_eq!(fixtures[0].dmx_address, (3, 1).into());
+        assert_eq!(fixtures[0].mapping.get_num_pixels(), 3);
+        assert_eq!(fixtures[0].world_pos(2), glam::Vec2::new(100.0, 5.0));
+
+        assert_eq!(fixtures[1].dmx_address, (30, 0).into());
+        assert_eq!(fixtures[1].mapping.get_num_pixels(), 20);
+
+        // the layers of a 3d model share one grid
+        let xml = r##"<custommodel name="Head" CustomModel="1,;,|,2;3," parm1="2" parm2="2"/>"##;
+        let fixtures = parse_xlights(xml, 10.0).unwrap();
+        assert_eq!(fixtures[0].mapping.get_size(), UVec2::new(2, 2));
+    }
+
+    #[test]
+    fn grid_layout() {
+        let text = "
+            # the mouth
+            [fixture]
+            name = mouth
+            groups = mouth, front
+            universe = 1
+            channel = 6
+            x = -20
+            dead = 2
+            1 2
+            4 3
+
+            [fixture]
+            1,,2
+        ";
+
+        let fixtures = parse_grid_layout(text).unwrap();
+        assert_eq!(fixtures.len(), 2);
+
+        assert_eq!(fixtures[0].name, "mouth");
+        assert_eq!(fixtures[0].groups, vec!["mouth", "front"]);
+        assert_eq!(fixtures[0].dmx_address, (6, 1).into());
+        assert_eq!(fixtures[0].mapping.get_grid_pos(3), UVec2::new(0, 1));
+        assert!(fixtures[0].mask.dead.contains(&1));
+        assert_eq!(fixtures[0].world_pos(0), glam::Vec2::new(-15.0, 5.0));
+
+        assert_eq!(fixtures[1].mapping.get_grid_pos(1), UVec2::new(2, 0));
+
+        assert!(parse_grid_layout("1 2").is_err());
+        assert!(parse_grid_layout("[fixture]\nspeed = 3").is_err());
+        assert!(parse_grid_layout("[fixture]\nuniverse = 300").is_err());
+        assert!(parse_grid_layout("[fixture]\nchannel = 1.5").is_err());
+    }
+}
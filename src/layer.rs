@@ -0,0 +1,116 @@
+use ecolor::Rgba;
+use serde::Deserialize;
+
+use crate::{draw::DrawContext, effect::{Effect, EffectEnum, Pixel}, mapping::LedMappingTrait, LedMappingInfo};
+
+/// How a layer is combined with the layers below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    #[default]
+    Add,
+    Screen,
+    Multiply,
+    /// Covers the layers below by its alpha
+    AlphaOver,
+    Max,
+    Difference,
+}
+
+impl BlendMode {
+    pub fn blend(&self, dst: Rgba, src: Rgba) -> Rgba {
+        let channels = |blend: fn(f32, f32) -> f32| {
+            Rgba::from_rgba_premultiplied(
+                blend(dst.r(), src.r()),
+                blend(dst.g(), src.g()),
+                blend(dst.b(), src.b()),
+                // coverage stacks like alpha over
+                src.a() + dst.a() * (1.0 - src.a()),
+            )
+        };
+
+        match self {
+            BlendMode::Add => dst + src,
+            BlendMode::Screen => channels(|dst, src| 1.0 - (1.0 - dst) * (1.0 - src)),
+            BlendMode::Multiply => channels(|dst, src| dst * src),
+            BlendMode::AlphaOver => src + dst * (1.0 - src.a()),
+            BlendMode::Max => channels(f32::max),
+            BlendMode::Difference => channels(|dst, src| (dst - src).abs()),
+        }
+    }
+}
+
+/// An effect in a stack and how it's combined with the layers below it
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub effect: EffectEnum,
+    pub opacity: f32,
+    pub blend: BlendMode,
+    /// The brightness of the mask limits where the layer shows
+    pub mask: Option<EffectEnum>,
+}
+
+impl From<EffectEnum> for Layer {
+    fn from(effect: EffectEnum) -> Self {
+        Self {
+            effect,
+            opacity: 1.0,
+            blend: BlendMode::Add,
+            mask: None,
+        }
+    }
+}
+
+/// Layers from the bottom up
+pub type EffectStack = Vec<Layer>;
+
+/// Draw one effect onto its own buffer
+fn render_effect(ctx: &DrawContext, fixture: &LedMappingInfo, pixels: &[Pixel], effect: &EffectEnum) -> Vec<Rgba> {
+    let mut buffer = vec![Rgba::TRANSPARENT; fixture.mapping.get_num_pixels()];
+    effect.render_fixture(ctx, fixture, pixels, &mut buffer);
+    buffer
+}
+
+/// Composite the layers of a stack onto `out`, which is indexed by pixel index
+pub fn render_stack(ctx: &DrawContext, fixture: &LedMappingInfo, pixels: &[Pixel], stack: &[Layer], out: &mut [Rgba]) {
+    for layer in stack {
+        let colors = render_effect(ctx, fixture, pixels, &layer.effect);
+        let mask = layer.mask.as_ref().map(|mask| render_effect(ctx, fixture, pixels, mask));
+
+        for pixel in pixels {
+            let i = pixel.index;
+
+            let mask_value = mask.as_ref().map_or(1.0, |mask| mask[i].intensity().clamp(0.0, 1.0));
+            let amount = layer.opacity * mask_value;
+
+            let blended = layer.blend.blend(out[i], colors[i]);
+            out[i] = out[i] * (1.0 - amount) + blended * amount;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Rgba;
+
+    use super::BlendMode;
+
+    #[test]
+    fn blend_modes() {
+        let dst = Rgba::from_rgb(0.5, 0.25, 0.0);
+        let src = Rgba::from_rgb(0.5, 0.5, 1.0);
+
+        let rgb = |color: Rgba| [color.r(), color.g(), color.b()];
+
+        assert_eq!(rgb(BlendMode::Add.blend(dst, src)), [1.0, 0.75, 1.0]);
+        assert_eq!(rgb(BlendMode::Screen.blend(dst, src)), [0.75, 0.625, 1.0]);
+        assert_eq!(rgb(BlendMode::Multiply.blend(dst, src)), [0.25, 0.125, 0.0]);
+        assert_eq!(rgb(BlendMode::AlphaOver.blend(dst, src)), [0.5, 0.5, 1.0]);
+        assert_eq!(rgb(BlendMode::Max.blend(dst, src)), [0.5, 0.5, 1.0]);
+        assert_eq!(rgb(BlendMode::Difference.blend(dst, src)), [0.0, 0.25, 1.0]);
+
+        // half transparent, so half of the layer below shows through
+        let half_src = src * 0.5;
+        assert_eq!(rgb(BlendMode::AlphaOver.blend(dst, half_src)), [0.5, 0.375, 0.5]);
+    }
+}
@@ -1,10 +1,12 @@
 use artnet_protocol::{ArtCommand, Output, PortAddress};
 use clap::Parser;
 
-use ecolor::Color32;
-use glam::Vec2;
-use mapping::{DmxAddress, LedMappingTrait, LedMappingEnum};
+use ecolor::Rgba;
+use glam::{Vec2, Vec3};
+use mapping::{DmxAddress, LedMappingTrait, LedMappingEnum, LedIndex};
 use matrix_mapping::MatrixMapping;
+use transform::{Canvas, Surface, Transform2D};
+use pixel_mask::{PixelMask, PixelState};
 use spin_sleep::{SpinSleeper};
 
 use std::{
@@ -15,16 +17,52 @@ use std::{
     thread::{self, yield_now, sleep},
     time::{Duration, Instant},
 };
-use draw::{DrawContext, draw_lightning};
+use draw::DrawContext;
+use effect::{create_effect, Effect, Pixel};
+use layer::Layer;
+use scene::{Scenes, Transition};
+use playlist::Playlist;
+use groups::GroupEffects;
+use spatial_index::{PixelRef, SpatialIndex};
+use config::Config;
+use power::PowerEstimate;
+use dither::TemporalDither;
+use feedback::CanvasBuffer;
+use palettes::Palettes;
 
 mod draw;
+mod effect;
+mod layer;
+mod scene;
+mod playlist;
+mod script;
+mod expression;
+mod image_effect;
+mod video;
+mod text;
+mod mouth;
+mod simulation;
+mod particles;
+mod feedback;
+mod palettes;
 mod mapping;
 mod matrix_mapping;
 mod strip_mapping;
 mod cli;
+mod transform;
+mod pixel_mask;
+mod custom_mapping;
+mod import;
+mod diagram;
+mod groups;
+mod spatial_index;
+mod calibration;
+mod config;
+mod power;
+mod dither;
 mod RLock;
 
-use crate::{draw::draw_blobs, strip_mapping::StripMapping};
+use crate::strip_mapping::StripMapping;
 
 mod pd_receive;
 
@@ -40,24 +78,75 @@ const ARTNET_ADDR: &str = &"192.168.11.4";
 
 #[derive(Debug, Clone)]
 pub struct LedMappingInfo {
+    name: String,
+    /// Tags used to pick the effects for the fixture
+    groups: Vec<String>,
     mapping: LedMappingEnum,
     dmx_address: DmxAddress,
-    pos_offset: Vec2,
+    transform: Transform2D,
+    surface: Surface,
+    mask: PixelMask,
 }
 
 impl LedMappingInfo {
-    fn new(mapping: LedMappingEnum, pos_offset: Vec2, dmx_address: DmxAddress) -> Self {
+    fn new(mapping: LedMappingEnum, transform: Transform2D, dmx_address: DmxAddress) -> Self {
         LedMappingInfo {
+            name: String::new(),
+            groups: vec![],
             mapping,
-            pos_offset,
-            dmx_address
+            transform,
+            dmx_address,
+            surface: Surface::Flat,
+            mask: PixelMask::default(),
         }
     }
+
+    fn with_name(self, name: impl Into<String>) -> Self {
+        Self { name: name.into(), ..self }
+    }
+
+    fn with_group(mut self, group: impl Into<String>) -> Self {
+        self.groups.push(group.into());
+        self
+    }
+
+    fn with_surface(self, surface: Surface) -> Self {
+        Self { surface, ..self }
+    }
+
+    /// Dmx address of a pixel, None if the pixel has no channels
+    pub fn pixel_dmx_address(&self, index: LedIndex) -> Option<DmxAddress> {
+        self.mask.dmx_index(index)
+            .map(|dmx_index| self.dmx_address.pixel_offset(dmx_index))
+    }
+
+    /// The first dmx address after this fixture
+    pub fn next_dmx_address(&self) -> DmxAddress {
+        self.dmx_address.pixel_offset(self.mask.num_dmx_pixels(self.mapping.get_num_pixels()))
+    }
+
+    /// Physical position of a pixel in mm
+    pub fn world_pos(&self, index: LedIndex) -> Vec2 {
+        self.transform.transform_point(self.mapping.get_pos(index))
+    }
+
+    /// Corners of the fixture grid in mm
+    pub fn world_outline(&self) -> [Vec2; 4] {
+        let size = self.mapping.get_size().as_vec2() * self.mapping.get_pitch();
+
+        [Vec2::ZERO, Vec2::new(size.x, 0.0), size, Vec2::new(0.0, size.y)]
+            .map(|corner| self.transform.transform_point(corner))
+    }
+
+    /// Physical position of a pixel on its surface in mm
+    pub fn world_pos_3d(&self, index: LedIndex) -> Vec3 {
+        self.surface.lift(self.world_pos(index))
+    }
 }
 
 fn print_mapping_info(mappings: &[LedMappingInfo]) {
-    for LedMappingInfo { mapping, dmx_address, pos_offset } in mappings {
-        println!("{mapping:?}\t {dmx_address:?}\t {pos_offset:?}");
+    for LedMappingInfo { name, groups, mapping, dmx_address, transform, surface, mask } in mappings {
+        println!("{name} {groups:?}\t {mapping:?}\t {dmx_address:?}\t {transform:?}\t {surface:?}\t {mask:?}");
     }
 }
 
@@ -65,11 +154,11 @@ pub fn mix(a: f32, b: f32, mix: f32) -> f32 {
     a*(1.0-mix) + b*mix
 }
 
-///Led data structured in the dmx alignment
+/// The framebuffer of a fixture, one linear color per pixel in mapping order
 #[derive(Clone)]
 pub struct LedData {
     info: LedMappingInfo,
-    data: Vec<Color32>
+    data: Vec<Rgba>
 }
 
 #[derive(Debug)]
@@ -78,108 +167,248 @@ pub struct LedFrameInfo {
     last_period: Duration,
     rendering_period: Duration,
     elapsed_since_pd_message: Duration,
+    power: PowerEstimate,
 }
 
-fn chained_led_mappings<'a, T: LedMappingTrait>(address: DmxAddress, make_mapping: impl Fn() -> T) -> impl Iterator<Item = (DmxAddress, T)> {
-    let first_strip = (address, make_mapping());
-
-    std::iter::successors(Some(first_strip), move |(addr, mapping)| {
-        let next_address = addr.pixel_offset(mapping.get_num_pixels());
-        Some((next_address, make_mapping()))
+/// Give each fixture the dmx address straight after the previous one
+fn chained_led_mappings(address: DmxAddress, fixtures: impl IntoIterator<Item=LedMappingInfo>) -> impl Iterator<Item=LedMappingInfo> {
+    fixtures.into_iter().scan(address, |next_address, mut fixture| {
+        fixture.dmx_address = *next_address;
+        *next_address = fixture.next_dmx_address();
+        Some(fixture)
     })
 }
 
-fn chained_led_matrices(width: usize, address: DmxAddress, pos: impl IntoIterator<Item=Vec2>) -> impl Iterator<Item=LedMappingInfo> {
-    chained_led_mappings(address, move || MatrixMapping::new(width))
-        .zip(pos)
-        .map(|((address, matrix), pos)| LedMappingInfo::new(matrix.into(), pos, address))
+fn chained_led_matrices<'a>(width: usize, address: DmxAddress, placements: impl IntoIterator<Item=(&'a str, Transform2D)> + 'a) -> impl Iterator<Item=LedMappingInfo> + 'a {
+    let matrices = placements.into_iter()
+        .map(move |(name, transform)| LedMappingInfo::new(MatrixMapping::new(width).into(), transform, address).with_name(name));
+
+    chained_led_mappings(address, matrices)
 }
 
-fn render_leds(ctx: DrawContext, matrices: &[LedMappingInfo], dmx_data: &mut HashMap<PortAddress, [u8; 512]>) -> Vec<LedData> {
-    let mut led_data: Vec<LedData> = Vec::with_capacity(matrices.len());
-    
-    for fixture in matrices {
-        let mapping = &fixture.mapping;
+/// Draw the effect stack of every fixture into its framebuffer, and the whole frame in canvas space
+/// Draws `frame` over again as well, for the effects that use it in the next frame
+fn render_frame(ctx: &DrawContext, canvas: &Canvas, scenes: &mut Scenes, matrices: &[LedMappingInfo], frame: &mut CanvasBuffer) -> Vec<LedData> {
+    scenes.update(ctx);
 
-        let mut pixels = vec![Color32::BLACK; mapping.get_num_pixels()];
+    frame.clear();
 
-        for i in 0..mapping.get_num_pixels() {
-            let dmx_target = fixture.dmx_address.pixel_offset(i);
-            let dmx_channel_start = dmx_target.channel;
+    let led_data = matrices.iter()
+        .map(|fixture| {
+            let pixels: Vec<Pixel> = (0..fixture.mapping.get_num_pixels())
+                .filter(|&i| fixture.mask.state(i) == PixelState::Active)
+                .map(|i| Pixel {
+                    index: i,
+                    pos: canvas.to_canvas(fixture.world_pos(i)),
+                    pos_3d: canvas.to_canvas_3d(fixture.world_pos_3d(i)),
+                })
+                .collect();
 
-            let dmx_universe_output = dmx_data
-                .entry(dmx_target.universe.into())
-                .or_insert([0; 512]);
+            let mut data = vec![Rgba::TRANSPARENT; fixture.mapping.get_num_pixels()];
 
-            let pos_i = mapping.get_pos(i);
-            let pos_f = pos_i.as_vec2();
+            scenes.render_fixture(ctx, fixture, &pixels, &mut data);
 
-            let center_offset = -glam::Vec2::new(0.5, 0.5)*16.0 + glam::Vec2::new(0.5,0.5);
+            for pixel in &pixels {
+                frame.add(pixel.pos, data[pixel.index]);
+            }
 
-            let pos_f_scale = match mapping {
-                LedMappingEnum::MatrixMapping(_) => Vec2::ONE,
-                LedMappingEnum::StripMapping(_) => Vec2::ONE*2.0,
-            };
+            LedData { info: fixture.clone(), data }
+        })
+        .collect();
 
-            let draw_pos = pos_f*pos_f_scale + center_offset + fixture.pos_offset;
+    led_data
+}
+
+/// Color correct and power limit a frame into the output level of each channel.
+/// The levels are kept as floats until the very end, so dim fades don't get stepped.
+fn post_process(config: &Config, matrices: &[LedMappingInfo], frame: &[LedData]) -> (Vec<Vec<[f32; 3]>>, PowerEstimate) {
+    let mut outputs: Vec<Vec<[f32; 3]>> = frame.iter()
+        .map(|LedData { info, data }| {
+            let color_profile = config.calibration.profile_for(info);
+            data.iter().map(|pixel| color_profile.apply(*pixel)).collect()
+        })
+        .collect();
+
+    let power_estimate = config.power.limit(matrices, &mut outputs);
+
+    (outputs, power_estimate)
+}
+
+/// Quantize the output levels and write them at the dmx address of each pixel
+fn pack_dmx(config: &Config, dither: &mut TemporalDither, matrices: &[LedMappingInfo], outputs: &[Vec<[f32; 3]>], dmx_data: &mut HashMap<PortAddress, [u8; 512]>) {
+    for (fixture_index, (fixture, output)) in matrices.iter().zip(outputs).enumerate() {
+        let output = dither.quantize(fixture_index, config.dithering.enabled_for(fixture), output);
 
-            let color = draw_blobs(&ctx, draw_pos)
-                + draw_lightning(&ctx, draw_pos);
+        for (i, rgb) in output.iter().enumerate() {
+            let Some(dmx_target) = fixture.pixel_dmx_address(i) else {
+                continue;
+            };
+            let dmx_channel_start = dmx_target.channel;
 
-            pixels[i] = color.into();
+            let dmx_universe_output = dmx_data
+                .entry(dmx_target.universe.into())
+                .or_insert([0; 512]);
 
             dmx_universe_output[dmx_channel_start..][..3]
-                .copy_from_slice(&pixels[i].to_array()[..3]);
+                .copy_from_slice(rgb);
         }
-
-        led_data.push(LedData { info: fixture.clone(), data: pixels });
     }
-    
-    led_data
 }
 
-fn main() {
-    let args = cli::Args::parse();
+/// The layout of the robot head
+fn built_in_layout() -> Vec<LedMappingInfo> {
+    let matrix_size = 160.0;
+    let strips_offset_y = 240.0;
+    let strip_pitch = 20.0;
+    // the strips wrap from the front of the mouth around the sides of the head
+    let head_surface = Surface::Cylinder { axis_x: 0.0, radius: 160.0 };
 
-    let pd_state = pd_receive::receive();
+    let mouth = |name, translation| (name, Transform2D::from_translation(translation));
 
-    let strips_offset_y = 32.0;
+    let strip = |name, length, inverted, dmx_address: DmxAddress, translation| {
+        LedMappingInfo::new(
+            StripMapping::new(length, inverted).with_pitch(strip_pitch).into(),
+            Transform2D::from_translation(translation),
+            dmx_address
+        )
+            .with_name(name)
+            .with_group("jaw_strips")
+            .with_surface(head_surface)
+    };
 
-    let matrices = 
-        chained_led_matrices(16, (0,44).into(), vec![Vec2::new(-8.0, 0.0), Vec2::new(8.0, 0.0)])
+    chained_led_matrices(16, (0,44).into(), vec![mouth("mouth_top_left", Vec2::new(-matrix_size, -80.0)), mouth("mouth_top_right", Vec2::new(0.0, -80.0))])
         .chain(
-            chained_led_matrices(16, (0,40).into(), vec![Vec2::new(-8.0, 16.0), Vec2::new(8.0, 16.0)])
+            chained_led_matrices(16, (0,40).into(), vec![mouth("mouth_bottom_left", Vec2::new(-matrix_size, 80.0)), mouth("mouth_bottom_right", Vec2::new(0.0, 80.0))])
         )
         .chain(
-            chained_led_matrices(16, (0,48).into(), vec![Vec2::new(0.0, 2.0*16.0), Vec2::new(0.0, 3.0*16.0)])
+            chained_led_matrices(16, (0,48).into(), vec![mouth("chin_top", Vec2::new(-80.0, 240.0)), mouth("chin_bottom", Vec2::new(-80.0, 400.0))])
         )
-        
-        .chain(std::iter::once(LedMappingInfo { 
-            mapping: StripMapping::new(6, false).into(), 
-            dmx_address: (0,36).into(), 
-            pos_offset: Vec2::new(16.0, strips_offset_y+1.0)
-        }))
-        
-        .chain(std::iter::once(LedMappingInfo { 
-            mapping: StripMapping::new(100, true).into(), 
-            dmx_address: (0,38).into(), 
-            pos_offset: Vec2::new(8.0, strips_offset_y)
-        }))
-
-        .chain(std::iter::once(LedMappingInfo { 
-            mapping: StripMapping::new(6, false).into(), 
-            dmx_address: (0,34).into(), 
-            pos_offset: Vec2::new(16.0, strips_offset_y+1.0)
-        }))
-        
-        .chain(std::iter::once(LedMappingInfo { 
-            mapping: StripMapping::new(100, true).into(), 
-            dmx_address: (0,32).into(), 
-            pos_offset: Vec2::new(8.0, strips_offset_y)
-        }))
+        .map(|matrix| matrix.with_group("mouth"))
+
+        .chain([
+            strip("jaw_a_short", 6, false, (0,36).into(), Vec2::new(80.0, strips_offset_y+10.0)),
+            strip("jaw_a", 100, true, (0,38).into(), Vec2::new(0.0, strips_offset_y)),
+            strip("jaw_b_short", 6, false, (0,34).into(), Vec2::new(80.0, strips_offset_y+10.0)),
+            strip("jaw_b", 100, true, (0,32).into(), Vec2::new(0.0, strips_offset_y)),
+        ])
+
+        .collect()
+}
+
+fn main() {
+    let args = cli::Args::parse();
+
+    let config = match &args.config {
+        Some(path) => Config::load(path)
+            .unwrap_or_else(|err| panic!("Failed to load the config from {path:?}\n{err}")),
+        None => Config::default(),
+    };
+
+    // effects are drawn in units of one matrix pixel, centered between the top matrices
+    let canvas = Canvas::new(Vec2::ZERO, matrix_mapping::DEFAULT_MATRIX_PITCH);
+
+    let matrices = match &args.layout {
+        Some(path) => import::load_layout(path)
+            .unwrap_or_else(|err| panic!("Failed to load the layout from {path:?}\n{err}")),
+        None => built_in_layout(),
+    };
+
+    if let Some(cli::Command::Diagram { output, #[cfg(feature = "png")] png }) = &args.command {
+        let svg = match diagram::wiring_svg(&matrices) {
+            Ok(svg) => svg,
+            Err(err) => {
+                eprintln!("Could not draw the wiring diagram: {err}");
+                std::process::exit(1);
+            },
+        };
+        std::fs::write(output, &svg).expect("Failed to write the diagram");
+        println!("Wrote wiring diagram to {output:?}");
+
+        #[cfg(feature = "png")]
+        if let Some(png) = png {
+            match diagram::svg_to_png(&svg) {
+                Ok(png_data) => {
+                    std::fs::write(png, png_data).expect("Failed to write the png diagram");
+                    println!("Wrote wiring diagram to {png:?}");
+                },
+                Err(err) => {
+                    eprintln!("Could not draw the png wiring diagram: {err}");
+                    std::process::exit(1);
+                },
+            }
+        }
+
+        return;
+    }
+
+    if let Some(cli::Command::Find { at, radius, polygon }) = &args.command {
+        let index = SpatialIndex::new(&matrices, &canvas);
+
+        let pixels = match (at, radius) {
+            (Some(at), Some(radius)) => index.in_radius(*at, *radius),
+            (Some(at), None) => index.nearest(*at).map(|(pixel, _)| pixel).into_iter().collect(),
+            (None, _) => index.in_polygon(polygon),
+        };
 
+        for PixelRef { fixture, index } in pixels {
+            let fixture = &matrices[fixture];
+            let pos = canvas.to_canvas(fixture.world_pos(index));
+            println!("{}\t pixel {index}\t {:?}\t canvas {pos:?}", fixture.name, fixture.pixel_dmx_address(index));
+        }
+
+        return;
+    }
+
+    let pd_state = pd_receive::receive();
+
+    let default_effects = match &config.effects {
+        Some(effects) => effects.build()
+            .unwrap_or_else(|err| panic!("Invalid effects in the config\n{err}")),
+        None => {
+            let stack = ["blobs", "lightning"].iter().map(|name| Layer::from(create_effect(name).unwrap())).collect();
+
+            GroupEffects::new(stack)
+        },
+    };
+
+    let mut scenes = config.scenes.iter()
+        .map(|(name, effects)| {
+            let effects = effects.build()
+                .unwrap_or_else(|err| panic!("Invalid effects for the {name} scene in the config\n{err}"));
+            (name.clone(), effects)
+        })
         .collect::<Vec<_>>();
 
+    if !config.scenes.contains_key("default") {
+        scenes.insert(0, ("default".to_owned(), default_effects));
+    }
+
+    let bounds = matrices.iter()
+        .flat_map(|fixture| fixture.world_outline())
+        .map(|corner| canvas.to_canvas(corner))
+        .fold((Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)), |(min, max), pos| (min.min(pos), max.max(pos)));
+
+    let mut scenes = Scenes::new(scenes, config.scene.transition, (bounds.0.x, bounds.1.x));
+    // drawn into in turns, so neither has to be allocated every frame
+    let mut previous_frame = CanvasBuffer::new(bounds);
+    let mut next_frame = CanvasBuffer::new(bounds);
+
+    let mut palettes = Palettes::new(&config.palettes, &config.palette)
+        .unwrap_or_else(|err| panic!("Invalid palettes in the config\n{err}"));
+
+    let start_scene = config.scene.start.as_deref().unwrap_or("default");
+    if !scenes.cut_to(start_scene) {
+        panic!("There is no scene called {start_scene:?} to start with, expected one of {:?}", scenes.names().collect::<Vec<_>>());
+    }
+
+    let mut playlist = config.playlist.clone().map(|playlist| Playlist::new(playlist, start_scene));
+
+    if let Some(playlist) = &playlist {
+        if let Some(missing) = playlist.scenes().find(|scene| !scenes.names().any(|name| name == *scene)) {
+            panic!("The playlist uses the scene {missing:?}, but there is no such scene");
+        }
+    }
+
     let matrices_clone = matrices.clone();
 
     // println!("DMX Squares: {matrices:#?}");
@@ -187,12 +416,13 @@ fn main() {
     
     #[cfg(feature = "jack")]
     let audio_rx = audio::get_audio();
-    // let mut noise = noise::Perlin::default();
+    let noise = noise::Perlin::default();
  
     let (led_data_tx, led_data_rx) = sync_channel(1);
     let (led_frame_info_tx, led_frame_info_rx) = sync_channel(1);
 
     let dmx_thread = thread::spawn(move || {
+        let mut dither = TemporalDither::new(&matrices);
 
         let start_time = Instant::now();
 
@@ -225,16 +455,77 @@ fn main() {
             }
         };
 
-        let process_led_frame = |pd_trail: &[f32]| {
+        // number of the last pd message that was acted on
+        let mut seen_messages = 0;
+
+        let mut process_led_frame = |pd_trail: &[f32]| {
             let mut dmx_data: HashMap<PortAddress, [u8; 512]> = Default::default();
 
             let elapsed = start_time.elapsed();
             let elapsed_seconds = elapsed.as_secs_f32();
+
+            {
+                let state = pd_state.read().unwrap();
+
+                let is_new = |number: u64| number > seen_messages;
+
+                if let Some((_, request)) = state.scene.as_ref().filter(|(number, _)| is_new(*number)) {
+                    let transition = Transition {
+                        style: request.style.unwrap_or(scenes.default_transition.style),
+                        duration: request.duration.unwrap_or(scenes.default_transition.duration),
+                    };
+
+                    if !scenes.switch(&request.name, Some(transition), elapsed_seconds) {
+                        eprintln!("Ignoring scene message: there is no scene called {:?}", request.name);
+                    }
+                }
+
+                if let Some((_, request)) = state.palette.as_ref().filter(|(number, _)| is_new(*number)) {
+                    if !palettes.switch(&request.name, request.duration, elapsed_seconds) {
+                        eprintln!("Ignoring palette message: there is no palette called {:?}, expected one of {:?}", request.name, palettes.names().collect::<Vec<_>>());
+                    }
+                }
+
+                if let Some((_, names)) = state.effect.as_ref().filter(|(number, _)| is_new(*number)) {
+                    match groups::stack_from_names(names) {
+                        Ok(mut stack) => {
+                            // picked effects keep the parameters that were sent for them before
+                            for ((effect, param), (_, value)) in &state.params {
+                                for layer in stack.iter_mut().filter(|layer| layer.effect.name() == effect) {
+                                    value.apply(&mut layer.effect, param);
+                                }
+                            }
+                            scenes.current_mut().set_default(stack);
+                        },
+                        Err(err) => eprintln!("Ignoring effect message: {err}"),
+                    }
+                }
+
+                for ((effect, param), (_, value)) in state.params.iter().filter(|(_, (number, _))| is_new(*number)) {
+                    for effects in scenes.all_mut() {
+                        effects.set_param(effect, param, value);
+                    }
+                }
+
+                seen_messages = state.message_count;
+
+                if let Some(playlist) = &mut playlist {
+                    let since_pd_message = state.last_message.map_or(elapsed, |last_message| last_message.elapsed());
+
+                    if let Some(scene) = playlist.update(state.voice_level, since_pd_message.as_secs_f32(), elapsed_seconds) {
+                        scenes.switch(scene, None, elapsed_seconds);
+                    }
+                }
+            }
+
+            palettes.update(elapsed_seconds);
             
             let ctx = DrawContext {
                 elapsed_seconds,
-                elapsed,
-                // noise,
+                noise: &noise,
+                bounds,
+                previous_frame: &previous_frame,
+                palette: palettes.current(),
 
                 #[cfg(feature = "jack")]
                 audio: audio_rx.recv().unwrap(),
@@ -242,7 +533,10 @@ fn main() {
                 audio: &pd_trail
             };
 
-            let led_data = render_leds(ctx, &matrices, &mut dmx_data);
+            let led_data = render_frame(&ctx, &canvas, &mut scenes, &matrices, &mut next_frame);
+            std::mem::swap(&mut previous_frame, &mut next_frame);
+            let (outputs, power_estimate) = post_process(&config, &matrices, &led_data);
+            pack_dmx(&config, &mut dither, &matrices, &outputs, &mut dmx_data);
 
             led_data_tx.try_send(led_data).ok();
 
@@ -261,6 +555,8 @@ fn main() {
                     },
                 }
             }
+
+            power_estimate
         };
 
         let target_loop_period = Duration::from_millis(1000 / 30);
@@ -275,7 +571,10 @@ fn main() {
             let elapsed_frame_time = last_start_frame_time.elapsed();
             last_start_frame_time = Instant::now();
 
-            let voice_level = pd_state.read().unwrap().voice_level;
+            let (voice_level, last_message) = {
+                let state = pd_state.read().unwrap();
+                (state.voice_level, state.last_message)
+            };
 
             for i in (0..pd_trail.len()).rev() {
                 let old = pd_trail[i];
@@ -290,15 +589,18 @@ fn main() {
                 // pd_trail[i] = new;
             }
             
-            // last_pd_message = Instant::now();
+            if let Some(last_message) = last_message {
+                last_pd_message = last_message;
+            }
             
-            process_led_frame(&pd_trail);
+            let power_estimate = process_led_frame(&pd_trail);
 
             match led_frame_info_tx.try_send(LedFrameInfo {
                             target_period: target_loop_period,
                             last_period: elapsed_frame_time,
                             rendering_period: last_start_frame_time.elapsed(),
-                            elapsed_since_pd_message: last_pd_message.elapsed()
+                            elapsed_since_pd_message: last_pd_message.elapsed(),
+                            power: power_estimate,
                         }) {
                 Err(std::sync::mpsc::TrySendError::Disconnected(_)) => {
                     panic!("Led data receiver disconnected!");
@@ -314,12 +616,13 @@ fn main() {
 
     if !args.headless && cfg!(feature = "gui") {
         #[cfg(feature = "gui")]
-        previs_ui::run_gui(matrices_clone, led_data_rx, led_frame_info_rx);
+        previs_ui::run_gui(matrices_clone, canvas, led_data_rx, led_frame_info_rx);
     } else {
         loop {
             let data = led_frame_info_rx.recv().unwrap();
 
             println!("{data:?}");
+            println!("power: {}", data.power);
             sleep(Duration::from_millis(1000));
         }
     }
@@ -1,9 +1,9 @@
 use std::fmt::Debug;
 
 use enum_dispatch::enum_dispatch;
-use glam::{UVec2};
+use glam::{UVec2, Vec2};
 
-use crate::{matrix_mapping::MatrixMapping, strip_mapping::StripMapping};
+use crate::{matrix_mapping::MatrixMapping, strip_mapping::StripMapping, custom_mapping::CustomMapping};
 
 /** Index of a pixel inside a given fixture.
  * Each pixel is made up of 3 dmx channels
@@ -13,10 +13,15 @@ use crate::{matrix_mapping::MatrixMapping, strip_mapping::StripMapping};
 pub type LedIndex = usize;
 
 /**
- * Position of the pixel in 2d space, starting at 0,0
+ * Position of the pixel in the fixture grid, starting at 0,0
  */
 pub type UPos = UVec2;
 
+/**
+ * Physical position of the pixel relative to the fixture origin, in mm
+ */
+pub type Pos = Vec2;
+
 pub const CHANNELS_PER_UNIVERSE: usize = 510;
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -61,8 +66,16 @@ impl From<(usize, u8)> for DmxAddress {
 #[enum_dispatch(LedMappingEnum)]
 /// Maps an led fixture to 2d coordinates
 pub trait LedMappingTrait: Clone {
-    /// Get the position of the pixel in 2d space
-    fn get_pos(&self, index: LedIndex) -> UPos;
+    /// Get the position of the pixel in the fixture grid
+    fn get_grid_pos(&self, index: LedIndex) -> UPos;
+
+    /// Distance between the centers of neighbouring pixels in mm
+    fn get_pitch(&self) -> Vec2;
+
+    /// Get the physical position of the pixel center in mm
+    fn get_pos(&self, index: LedIndex) -> Pos {
+        (self.get_grid_pos(index).as_vec2() + Vec2::splat(0.5)) * self.get_pitch()
+    }
 
     //max size of the whole fixture
     fn get_size(&self) -> UVec2;
@@ -74,15 +87,17 @@ pub trait LedMappingTrait: Clone {
 #[enum_dispatch]
 #[derive(Clone)]
 pub enum LedMappingEnum {
-    MatrixMapping,
-    StripMapping
+    Matrix(MatrixMapping),
+    Strip(StripMapping),
+    Custom(CustomMapping),
 }
 
 impl Debug for LedMappingEnum {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::MatrixMapping(arg0) => arg0.fmt(f),
-            Self::StripMapping(arg0) => arg0.fmt(f),
+            Self::Matrix(arg0) => arg0.fmt(f),
+            Self::Strip(arg0) => arg0.fmt(f),
+            Self::Custom(arg0) => arg0.fmt(f),
         }
     }
 }
\ No newline at end of file
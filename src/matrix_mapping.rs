@@ -2,28 +2,30 @@ use glam::{Vec2, UVec2};
 
 use crate::mapping::*;
 
+/// Pixel pitch of the 16x16 WS2812 panels (160mm square)
+pub const DEFAULT_MATRIX_PITCH: f32 = 10.0;
+
 #[derive(Debug, Clone, Copy)]
 pub struct MatrixMapping {
     pub width: LedIndex,
+    pub pitch: f32,
 }
 
 impl MatrixMapping {
     pub fn new(width: LedIndex) -> Self {
-        Self { width }
+        Self { width, pitch: DEFAULT_MATRIX_PITCH }
     }
 }
 
 impl Default for MatrixMapping {
     fn default() -> Self {
-        Self {
-            width: 16
-        }
+        Self::new(16)
     }
 }
 
 //todo: probably separate these concerns
 impl LedMappingTrait for MatrixMapping {
-    fn get_pos(&self, index: LedIndex) -> UPos {
+    fn get_grid_pos(&self, index: LedIndex) -> UPos {
         let mut x = index % self.width;
         let y = index / self.width;
 
@@ -36,6 +38,10 @@ impl LedMappingTrait for MatrixMapping {
         [y as u32, x as u32].into()
     }
 
+    fn get_pitch(&self) -> Vec2 {
+        Vec2::splat(self.pitch)
+    }
+
     fn get_size(&self) -> UVec2 {
         UVec2::new(self.width as u32, self.width as u32)
     }
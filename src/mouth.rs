@@ -0,0 +1,209 @@
+use ecolor::Rgba;
+use glam::Vec2;
+
+use crate::{draw::DrawContext, effect::{parse_color, Effect, Pixel}};
+
+/// The outline of a mouth, in units of the mouth size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MouthShape {
+    pub width: f32,
+    /// Of the opening between the lips
+    pub height: f32,
+    /// 2 is an ellipse, higher gets closer to a rectangle
+    pub roundness: f32,
+    /// How far the corners are raised, negative for a frown
+    pub smile: f32,
+}
+
+impl MouthShape {
+    fn mix(self, other: Self, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+
+        Self {
+            width: mix(self.width, other.width),
+            height: mix(self.height, other.height),
+            roundness: mix(self.roundness, other.roundness),
+            smile: mix(self.smile, other.smile),
+        }
+    }
+
+    /// Whether a point is inside the shape grown by `grow` on every side
+    fn contains(&self, pos: Vec2, grow: f32) -> bool {
+        let half_width = self.width / 2.0;
+        let radius = Vec2::new(half_width + grow, self.height / 2.0 + grow);
+
+        // y points down, so raised corners are further up
+        let center_y = -self.smile * (pos.x / half_width.max(f32::EPSILON)).powi(2);
+        let relative = (Vec2::new(pos.x, pos.y - center_y) / radius).abs();
+
+        relative.x.powf(self.roundness) + relative.y.powf(self.roundness) <= 1.0
+    }
+}
+
+/// The mouth shapes that can be picked by name, like visemes for lip sync
+pub const SHAPES: &[(&str, MouthShape)] = &[
+    ("closed", MouthShape { width: 1.0, height: 0.0, roundness: 2.0, smile: 0.0 }),
+    ("open", MouthShape { width: 0.9, height: 0.4, roundness: 2.5, smile: 0.0 }),
+    ("wide", MouthShape { width: 1.15, height: 0.15, roundness: 3.0, smile: 0.05 }),
+    ("o", MouthShape { width: 0.45, height: 0.45, roundness: 2.0, smile: 0.0 }),
+    ("u", MouthShape { width: 0.3, height: 0.2, roundness: 2.0, smile: 0.0 }),
+    ("smile", MouthShape { width: 1.0, height: 0.08, roundness: 2.0, smile: 0.2 }),
+];
+
+fn shape(name: &str) -> Option<MouthShape> {
+    SHAPES.iter().find(|(shape_name, _)| *shape_name == name).map(|(_, shape)| *shape)
+}
+
+/// Picks a shape from the voice level
+fn auto_shape(level: f32) -> MouthShape {
+    let name = match level {
+        level if level < 0.05 => "closed",
+        level if level < 0.2 => "wide",
+        level if level < 0.5 => "open",
+        _ => "o",
+    };
+
+    shape(name).unwrap()
+}
+
+/// A lip synced mouth, morphing between shapes picked over the network or by the voice level
+#[derive(Debug, Clone)]
+pub struct MouthEffect {
+    /// None follows the voice
+    target: Option<MouthShape>,
+    current: MouthShape,
+    /// Seconds it takes to get most of the way to a new shape
+    transition: f32,
+    /// Multiplies the voice level in auto mode
+    sensitivity: f32,
+    /// Relative to the width of the canvas
+    size: f32,
+    /// Moves the mouth away from the center of the canvas, in canvas units
+    offset: Vec2,
+    /// Thickness of the lips in units of the mouth size
+    lips: f32,
+    lip_color: Rgba,
+    inside_color: Rgba,
+    brightness: f32,
+    last_update: Option<f32>,
+    /// Center and size of the mouth in canvas space this frame
+    center: Vec2,
+    scale: f32,
+}
+
+impl Default for MouthEffect {
+    fn default() -> Self {
+        Self {
+            target: None,
+            current: shape("closed").unwrap(),
+            transition: 0.08,
+            sensitivity: 1.0,
+            size: 0.8,
+            offset: Vec2::ZERO,
+            lips: 0.06,
+            lip_color: Rgba::from_srgba_unmultiplied(255, 40, 90, 255),
+            inside_color: Rgba::from_srgba_unmultiplied(40, 0, 10, 255),
+            brightness: 1.0,
+            last_update: None,
+            center: Vec2::ZERO,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Effect for MouthEffect {
+    fn name(&self) -> &str {
+        "mouth"
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        let delta = self.last_update.map_or(0.0, |last_update| (ctx.elapsed_seconds - last_update).max(0.0));
+        self.last_update = Some(ctx.elapsed_seconds);
+
+        let target = self.target.unwrap_or_else(|| {
+            // the newest few samples, which works for the voice level trail and raw audio
+            let level = ctx.audio.iter().take(4).fold(0.0f32, |level, sample| level.max(sample.abs()));
+            auto_shape(level * self.sensitivity)
+        });
+
+        // eases out the same way whatever the frame rate
+        let t = if self.transition > 0.0 { 1.0 - (-delta / self.transition).exp() } else { 1.0 };
+        self.current = self.current.mix(target, t);
+
+        let (min, max) = ctx.bounds;
+        self.center = (min + max) / 2.0 + self.offset;
+        self.scale = (max.x - min.x) * self.size;
+    }
+
+    fn render_pixel(&self, _ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        let pos = (pixel.pos - self.center) / self.scale.max(f32::EPSILON);
+
+        let color = if self.current.contains(pos, 0.0) {
+            self.inside_color
+        } else if self.current.contains(pos, self.lips) {
+            self.lip_color
+        } else {
+            return Rgba::TRANSPARENT;
+        };
+
+        color * self.brightness
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "transition" => self.transition = value,
+            "sensitivity" => self.sensitivity = value,
+            "size" => self.size = value,
+            "x" => self.offset.x = value,
+            "y" => self.offset.y = value,
+            "lips" => self.lips = value,
+            "brightness" => self.brightness = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_text_param(&mut self, name: &str, value: &str) -> bool {
+        match (name, shape(value), parse_color(value)) {
+            ("shape", _, _) if value == "auto" => self.target = None,
+            ("shape", Some(shape), _) => self.target = Some(shape),
+            ("color", _, Some(color)) => self.lip_color = color,
+            ("inside", _, Some(color)) => self.inside_color = color,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec3};
+
+    use super::{shape, MouthEffect};
+    use crate::{draw::{test_context, DrawContext}, effect::{Effect, Pixel}};
+
+    #[test]
+    fn shapes() {
+        let mut effect = MouthEffect::default();
+
+        let ctx = |seconds, audio| DrawContext { audio, ..test_context(seconds) };
+        // the mouth is 16 units wide, so this is 0.125 below the center in mouth units
+        let below_center = Pixel { index: 0, pos: Vec2::new(0.0, 2.0), pos_3d: Vec3::ZERO };
+
+        effect.update(&ctx(0.0, &[]));
+        assert_eq!(effect.current, shape("closed").unwrap());
+        assert_eq!(effect.render_pixel(&ctx(0.0, &[]), &below_center).a(), 0.0);
+
+        // loud enough to open the mouth
+        effect.update(&ctx(1.0, &[0.3]));
+        assert!((effect.current.height - shape("open").unwrap().height).abs() < 0.001);
+        assert_eq!(effect.render_pixel(&ctx(1.0, &[0.3]), &below_center), effect.inside_color);
+
+        // picked over the network, most of the way there after one transition
+        assert!(effect.set_text_param("shape", "closed"));
+        assert!(!effect.set_text_param("shape", "grin"));
+        let height = effect.current.height;
+        effect.update(&ctx(1.08, &[0.3]));
+        assert!((effect.current.height - height / std::f32::consts::E).abs() < 0.001);
+    }
+}
@@ -0,0 +1,298 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use ecolor::{linear_from_gamma, Color32, Rgba};
+use serde::Deserialize;
+
+use crate::effect::parse_color;
+
+/// The palettes that can always be picked by name. They wrap around, so each ends on a
+/// color that blends back into the first.
+const BUILTIN: &[(&str, &[&str])] = &[
+    ("rainbow", &["#ff0000", "#ffff00", "#00ff00", "#00ffff", "#0000ff", "#ff00ff"]),
+    ("fire", &["#ff1000", "#ff6000", "#ffc020", "#ff6000"]),
+    ("ocean", &["#0020ff", "#0090ff", "#00ffd0", "#0090ff"]),
+    ("sunset", &["#ff2060", "#ff7030", "#ffd050", "#8030ff"]),
+    ("neon", &["#ff00c0", "#8000ff", "#00e0ff", "#8000ff"]),
+    ("ice", &["#ffffff", "#80d0ff", "#2050ff", "#80d0ff"]),
+    ("mono", &["#ffffff"]),
+];
+
+/// A gradient that effects map their hue through, so the whole show can be re-themed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    /// Positions in 0..1 in order, with linear colors
+    stops: Vec<(f32, Rgba)>,
+}
+
+impl Default for Palette {
+    /// The hue wheel, which looks the same as if there was no palette
+    fn default() -> Self {
+        Self::from_hex(BUILTIN[0].1).unwrap()
+    }
+}
+
+impl Palette {
+    /// Colors spread out evenly, the last one blends back into the first
+    pub fn from_colors(colors: Vec<Rgba>) -> Option<Self> {
+        if colors.is_empty() {
+            return None;
+        }
+
+        let count = colors.len() as f32;
+        let stops = colors.into_iter().enumerate().map(|(i, color)| (i as f32 / count, color)).collect();
+
+        Some(Self { stops })
+    }
+
+    fn from_hex(colors: &[&str]) -> Option<Self> {
+        Self::from_colors(colors.iter().map(|color| parse_color(color)).collect::<Option<_>>()?)
+    }
+
+    /// Linear color at a position, which wraps around every 1
+    pub fn sample(&self, t: f32) -> Rgba {
+        let t = t.rem_euclid(1.0);
+        let count = self.stops.len();
+
+        // the stops on either side, wrapping around at the ends
+        let next = self.stops.iter().position(|(pos, _)| *pos > t).unwrap_or(0);
+        let previous = (next + count - 1) % count;
+
+        let (mut previous_pos, previous_color) = self.stops[previous];
+        let (mut next_pos, next_color) = self.stops[next];
+
+        if previous_pos > t {
+            previous_pos -= 1.0;
+        }
+        if next_pos <= t {
+            next_pos += 1.0;
+        }
+
+        let span = next_pos - previous_pos;
+        let mix = if span > 0.0 { (t - previous_pos) / span } else { 0.0 };
+
+        previous_color * (1.0 - mix) + next_color * mix
+    }
+
+    /// Like `HsvaGamma`, with the hue picked from the palette instead of the color wheel
+    pub fn shade(&self, h: f32, s: f32, v: f32, a: f32) -> Rgba {
+        let color = self.sample(h);
+        let (s, v) = (s.clamp(0.0, 1.0), linear_from_gamma(v));
+
+        let channel = |channel: f32| v * (1.0 - s + s * channel);
+        Rgba::from_rgba_unmultiplied(channel(color.r()), channel(color.g()), channel(color.b()), a)
+    }
+
+    /// Blended towards another palette by `amount`
+    fn mix(&self, other: &Self, amount: f32) -> Self {
+        let mut positions: Vec<f32> = self.stops.iter().chain(&other.stops).map(|(pos, _)| *pos).collect();
+        positions.sort_by(f32::total_cmp);
+        positions.dedup();
+
+        let stops = positions.into_iter()
+            .map(|pos| (pos, self.sample(pos) * (1.0 - amount) + other.sample(pos) * amount))
+            .collect();
+
+        Self { stops }
+    }
+
+    /// An image with the gradient from left to right, or a text file with one color per line
+    fn load(path: &Path) -> Result<Self, String> {
+        let is_text = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("txt"));
+
+        let colors = if is_text {
+            let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+
+            text.lines()
+                .map(str::trim)
+                .enumerate()
+                .filter(|(_, line)| !line.is_empty())
+                .map(|(i, line)| parse_color(line).ok_or_else(|| format!("Line {} is not a color: {line:?}", i + 1)))
+                .collect::<Result<_, _>>()?
+        } else {
+            let image = image::open(path).map_err(|err| err.to_string())?.into_rgb8();
+            let row = image.height() / 2;
+
+            (0..image.width())
+                .map(|x| {
+                    let [r, g, b] = image.get_pixel(x, row).0;
+                    Color32::from_rgb(r, g, b).into()
+                })
+                .collect()
+        };
+
+        Self::from_colors(colors).ok_or_else(|| "There are no colors in it".to_owned())
+    }
+}
+
+/// A palette in the config, as a list of colors or the path of a file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteSpec {
+    Colors(Vec<String>),
+    File(PathBuf),
+}
+
+impl PaletteSpec {
+    pub fn create(&self) -> Result<Palette, String> {
+        match self {
+            PaletteSpec::Colors(colors) => {
+                let colors = colors.iter()
+                    .map(|color| parse_color(color).ok_or_else(|| format!("{color:?} is not a color")))
+                    .collect::<Result<_, _>>()?;
+
+                Palette::from_colors(colors).ok_or_else(|| "There are no colors in it".to_owned())
+            },
+            PaletteSpec::File(path) => Palette::load(path),
+        }
+    }
+}
+
+/// Which palette is used first and how long switching to another one takes by default
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaletteSettings {
+    pub start: String,
+    /// In seconds
+    pub transition: f32,
+}
+
+impl Default for PaletteSettings {
+    fn default() -> Self {
+        Self {
+            start: BUILTIN[0].0.to_owned(),
+            transition: 2.0,
+        }
+    }
+}
+
+struct ActiveBlend {
+    from: Palette,
+    start_seconds: f32,
+    duration: f32,
+}
+
+/// Named palettes and the one effects currently use, blending from one to the next
+pub struct Palettes {
+    palettes: Vec<(String, Palette)>,
+    target: usize,
+    /// Used when a switch doesn't pick its own duration
+    pub default_transition: f32,
+    active: Option<ActiveBlend>,
+    current: Palette,
+}
+
+impl Palettes {
+    /// The built in palettes and the ones from the config, which can replace them
+    pub fn new(specs: &BTreeMap<String, PaletteSpec>, settings: &PaletteSettings) -> Result<Self, String> {
+        let mut palettes: Vec<(String, Palette)> = BUILTIN.iter()
+            .map(|(name, colors)| (name.to_string(), Palette::from_hex(colors).unwrap()))
+            .collect();
+
+        for (name, spec) in specs {
+            let palette = spec.create().map_err(|err| format!("Invalid palette {name:?}: {err}"))?;
+
+            match palettes.iter_mut().find(|(existing, _)| existing == name) {
+                Some((_, existing)) => *existing = palette,
+                None => palettes.push((name.clone(), palette)),
+            }
+        }
+
+        let target = palettes.iter().position(|(name, _)| *name == settings.start)
+            .ok_or_else(|| format!("There is no palette called {:?} to start with", settings.start))?;
+
+        Ok(Self {
+            current: palettes[target].1.clone(),
+            palettes,
+            target,
+            default_transition: settings.transition,
+            active: None,
+        })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.palettes.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Starts blending from the current palette into another one. Returns false if there is
+    /// no palette with that name.
+    pub fn switch(&mut self, name: &str, duration: Option<f32>, elapsed_seconds: f32) -> bool {
+        let Some(target) = self.palettes.iter().position(|(palette_name, _)| palette_name == name) else {
+            return false;
+        };
+
+        self.target = target;
+        self.active = Some(ActiveBlend {
+            from: self.current.clone(),
+            start_seconds: elapsed_seconds,
+            duration: duration.unwrap_or(self.default_transition),
+        });
+
+        true
+    }
+
+    /// Moves the blend along, called once per frame
+    pub fn update(&mut self, elapsed_seconds: f32) {
+        let Some(active) = &self.active else {
+            return;
+        };
+
+        let target = &self.palettes[self.target].1;
+        let progress = if active.duration > 0.0 {
+            ((elapsed_seconds - active.start_seconds) / active.duration).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        if progress >= 1.0 {
+            self.current = target.clone();
+            self.active = None;
+        } else {
+            self.current = active.from.mix(target, progress);
+        }
+    }
+
+    /// The palette effects draw with this frame
+    pub fn current(&self) -> &Palette {
+        &self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ecolor::{HsvaGamma, Rgba};
+
+    use super::{Palette, PaletteSettings, PaletteSpec, Palettes};
+
+    #[test]
+    fn palettes() {
+        // the default looks like the color wheel
+        let rainbow = Palette::default();
+        for (h, s, v, a) in [(0.1, 1.0, 1.0, 1.0), (0.75, 0.5, 0.3, 0.15), (1.3, 0.0, 0.8, 1.0)] {
+            let expected: Rgba = HsvaGamma { h, s, v, a }.into();
+            let color = rainbow.shade(h, s, v, a);
+            assert!((0..4).all(|i| (color[i] - expected[i]).abs() < 1e-5), "{color:?} {expected:?}");
+        }
+
+        // wraps around from the last color into the first
+        let palette = PaletteSpec::Colors(vec!["#000000".into(), "#ffffff".into()]).create().unwrap();
+        assert_eq!(palette.sample(0.25), Rgba::from_gray(0.5));
+        assert_eq!(palette.sample(0.75), Rgba::from_gray(0.5));
+        assert_eq!(palette.sample(-0.5), Rgba::WHITE);
+        assert!(PaletteSpec::Colors(vec!["red".into()]).create().is_err());
+
+        let specs = BTreeMap::from([("night".to_owned(), PaletteSpec::Colors(vec!["#000000".into()]))]);
+        let settings = PaletteSettings { start: "mono".into(), transition: 2.0 };
+        let mut palettes = Palettes::new(&specs, &settings).unwrap();
+        assert_eq!(palettes.current().sample(0.3), Rgba::WHITE);
+
+        // halfway there after half the transition
+        assert!(palettes.switch("night", None, 10.0));
+        assert!(!palettes.switch("day", None, 10.0));
+        palettes.update(11.0);
+        assert_eq!(palettes.current().sample(0.3), Rgba::from_gray(0.5));
+        palettes.update(12.0);
+        assert_eq!(palettes.current().sample(0.3), Rgba::BLACK);
+    }
+}
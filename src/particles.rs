@@ -0,0 +1,267 @@
+use std::{collections::HashMap, f32::consts::TAU};
+
+use ecolor::Rgba;
+use glam::{IVec2, Vec2};
+use rand::Rng;
+
+use crate::{draw::DrawContext, effect::{parse_color, Effect, Pixel}};
+
+/// Emission stops at this many particles, so a long shout can't stall the render loop
+const MAX_PARTICLES: usize = 2000;
+
+/// Longer frames are simulated as if they took this long, so particles don't jump
+const MAX_TIME_STEP: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Particle {
+    pos: Vec2,
+    velocity: Vec2,
+    /// In seconds
+    age: f32,
+    lifetime: f32,
+}
+
+/// Where particles come from and how they start out
+#[derive(Debug, Clone)]
+struct Emitter {
+    /// Particles per second
+    rate: f32,
+    /// Added particles per second for each unit the voice level is over the threshold
+    audio: f32,
+    threshold: f32,
+    /// Relative to the center of the canvas, in canvas units
+    offset: Vec2,
+    /// Particles start anywhere within this radius
+    radius: f32,
+    /// In canvas units per second
+    speed: f32,
+    /// Direction particles fly in, in degrees clockwise from the right
+    angle: f32,
+    /// Width of the cone around the angle, 360 goes all around
+    cone: f32,
+    /// In seconds, each particle lives up to 50% longer or shorter
+    lifetime: f32,
+    /// Fraction of a particle left over from the last frames
+    pending: f32,
+}
+
+impl Emitter {
+    /// How many particles to start this frame
+    fn count(&mut self, voice_level: f32, delta: f32) -> usize {
+        let rate = self.rate + (voice_level - self.threshold).max(0.0) * self.audio;
+
+        self.pending += rate.max(0.0) * delta;
+        let count = self.pending.floor();
+        self.pending -= count;
+
+        count as usize
+    }
+
+    fn emit(&self, center: Vec2, rng: &mut impl Rng) -> Particle {
+        let spawn_angle = rng.gen_range(0.0..TAU);
+        let spawn_offset = Vec2::new(spawn_angle.cos(), spawn_angle.sin()) * self.radius * rng.gen::<f32>().sqrt();
+
+        let half_cone = self.cone.clamp(0.0, 360.0).to_radians() / 2.0;
+        let angle = self.angle.to_radians() + rng.gen_range(-half_cone..=half_cone);
+        let speed = self.speed * rng.gen_range(0.5..=1.0);
+
+        Particle {
+            pos: center + self.offset + spawn_offset,
+            velocity: Vec2::new(angle.cos(), angle.sin()) * speed,
+            age: 0.0,
+            lifetime: self.lifetime * rng.gen_range(0.5..=1.5),
+        }
+    }
+}
+
+/// Sparks flying out of an emitter, more of them the louder the voice
+#[derive(Debug, Clone)]
+pub struct Particles {
+    particles: Vec<Particle>,
+    /// Indices of the particles in each cell of `size` canvas units, for splatting
+    buckets: HashMap<IVec2, Vec<usize>>,
+    emitter: Emitter,
+    /// Pulls particles down, in canvas units per second squared
+    gravity: f32,
+    /// Fraction of the velocity lost per second
+    drag: f32,
+    /// Radius a particle is drawn with, in canvas units
+    size: f32,
+    color: Rgba,
+    /// Particles change to this color over their life while fading out
+    fade_to: Rgba,
+    brightness: f32,
+    last_update: Option<f32>,
+}
+
+impl Default for Particles {
+    fn default() -> Self {
+        Self {
+            particles: vec![],
+            buckets: HashMap::new(),
+            emitter: Emitter {
+                rate: 0.0,
+                audio: 400.0,
+                threshold: 0.1,
+                offset: Vec2::ZERO,
+                radius: 2.0,
+                speed: 25.0,
+                angle: -90.0,
+                cone: 360.0,
+                lifetime: 1.0,
+                pending: 0.0,
+            },
+            gravity: 30.0,
+            drag: 1.0,
+            size: 1.5,
+            color: Rgba::from_srgba_unmultiplied(255, 230, 120, 255),
+            fade_to: Rgba::from_srgba_unmultiplied(255, 40, 0, 255),
+            brightness: 1.0,
+            last_update: None,
+        }
+    }
+}
+
+impl Particles {
+    fn simulate(&mut self, delta: f32) {
+        let drag = (1.0 - self.drag * delta).max(0.0);
+
+        for particle in &mut self.particles {
+            // y points down
+            particle.velocity.y += self.gravity * delta;
+            particle.velocity *= drag;
+            particle.pos += particle.velocity * delta;
+            particle.age += delta;
+        }
+
+        self.particles.retain(|particle| particle.age < particle.lifetime);
+    }
+
+    fn bucket(&self, pos: Vec2) -> IVec2 {
+        (pos / self.size.max(0.1)).floor().as_ivec2()
+    }
+}
+
+impl Effect for Particles {
+    fn name(&self) -> &str {
+        "particles"
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        let delta = self.last_update.map_or(0.0, |last_update| (ctx.elapsed_seconds - last_update).clamp(0.0, MAX_TIME_STEP));
+        self.last_update = Some(ctx.elapsed_seconds);
+
+        self.simulate(delta);
+
+        let voice_level = ctx.audio.first().map_or(0.0, |level| level.abs());
+        let count = self.emitter.count(voice_level, delta).min(MAX_PARTICLES.saturating_sub(self.particles.len()));
+
+        let (min, max) = ctx.bounds;
+        let mut rng = rand::thread_rng();
+        for _ in 0..count {
+            self.particles.push(self.emitter.emit((min + max) / 2.0, &mut rng));
+        }
+
+        self.buckets.clear();
+        for (i, particle) in self.particles.iter().enumerate() {
+            self.buckets.entry(self.bucket(particle.pos)).or_default().push(i);
+        }
+    }
+
+    /// Adds up the particles in reach, fading out towards their edge
+    fn render_pixel(&self, _ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        let bucket = self.bucket(pixel.pos);
+        let mut color = Rgba::TRANSPARENT;
+
+        for offset in (-1..=1).flat_map(|y| (-1..=1).map(move |x| IVec2::new(x, y))) {
+            for particle in self.buckets.get(&(bucket + offset)).into_iter().flatten().map(|&i| &self.particles[i]) {
+                let distance = particle.pos.distance(pixel.pos) / self.size.max(0.1);
+                if distance >= 1.0 {
+                    continue;
+                }
+
+                let life = particle.age / particle.lifetime;
+                let particle_color = self.color * (1.0 - life) + self.fade_to * life;
+
+                color = color + particle_color * ((1.0 - distance).powi(2) * (1.0 - life));
+            }
+        }
+
+        color * self.brightness
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "rate" => self.emitter.rate = value,
+            "audio" => self.emitter.audio = value,
+            "threshold" => self.emitter.threshold = value,
+            "x" => self.emitter.offset.x = value,
+            "y" => self.emitter.offset.y = value,
+            "radius" => self.emitter.radius = value,
+            "speed" => self.emitter.speed = value,
+            "angle" => self.emitter.angle = value,
+            "cone" => self.emitter.cone = value,
+            "lifetime" => self.emitter.lifetime = value,
+            "gravity" => self.gravity = value,
+            "drag" => self.drag = value,
+            "size" => self.size = value,
+            "brightness" => self.brightness = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_text_param(&mut self, name: &str, value: &str) -> bool {
+        match (name, parse_color(value)) {
+            ("color", Some(color)) => self.color = color,
+            ("fade_to", Some(color)) => self.fade_to = color,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Rgba;
+    use glam::{Vec2, Vec3};
+
+    use super::{Particle, Particles};
+    use crate::{draw::{test_context, DrawContext}, effect::{Effect, Pixel}};
+
+    #[test]
+    fn particles() {
+        let mut effect = Particles::default();
+        assert!(effect.set_param("audio", 100.0));
+
+        let ctx = |seconds, audio| DrawContext { audio, ..test_context(seconds) };
+
+        // nothing below the threshold, then 50 particles per second over it
+        effect.update(&ctx(0.0, &[0.6]));
+        effect.update(&ctx(0.1, &[0.05]));
+        assert!(effect.particles.is_empty());
+        effect.update(&ctx(0.2, &[0.6]));
+        assert_eq!(effect.particles.len(), 5);
+
+        // falling and slowing down
+        effect.particles = vec![Particle { pos: Vec2::ZERO, velocity: Vec2::new(10.0, 0.0), age: 0.0, lifetime: 1.0 }];
+        effect.gravity = 10.0;
+        effect.drag = 0.5;
+        effect.simulate(0.1);
+        assert_eq!(effect.particles[0].velocity, Vec2::new(9.5, 0.95));
+
+        effect.simulate(1.0);
+        assert!(effect.particles.is_empty());
+
+        // drawn brightest in the middle, nothing outside its size
+        effect.particles = vec![Particle { pos: Vec2::ZERO, velocity: Vec2::ZERO, age: 0.0, lifetime: 1.0 }];
+        effect.update(&ctx(0.2, &[]));
+        let pixel = |x| Pixel { index: 0, pos: Vec2::new(x, 0.0), pos_3d: Vec3::ZERO };
+        assert_eq!(effect.render_pixel(&ctx(0.2, &[]), &pixel(0.0)), effect.color);
+        assert_eq!(effect.render_pixel(&ctx(0.2, &[]), &pixel(2.0)), Rgba::TRANSPARENT);
+
+        // still a color on top of a particle without a size
+        assert!(effect.set_param("size", 0.0));
+        assert_eq!(effect.render_pixel(&ctx(0.2, &[]), &pixel(0.0)), effect.color);
+    }
+}
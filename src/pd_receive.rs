@@ -1,39 +1,116 @@
-use std::{net::{Ipv4Addr, UdpSocket, SocketAddr}, sync::{mpsc::{sync_channel, TrySendError}, RwLock, Arc}};
+use std::{collections::HashMap, time::Instant, net::{Ipv4Addr, UdpSocket, SocketAddr}, sync::{mpsc::{sync_channel, TrySendError}, RwLock, Arc}};
 
-use crate::RLock::{RLock, split_arwlock};
+use crate::{RLock::{RLock, split_arwlock}, effect::ParamValue, scene::TransitionStyle};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum PdPacket {
-    VoiceLevel(f32)
+    VoiceLevel(f32),
+    /// Names of the effects to draw on fixtures without a group
+    Effect(Vec<String>),
+    Param { effect: String, param: String, value: ParamValue },
+    Scene(SceneRequest),
+    Palette(PaletteRequest),
+}
+
+/// Switch to a scene, optionally overriding the configured transition
+#[derive(Debug, PartialEq, Clone)]
+pub struct SceneRequest {
+    pub name: String,
+    pub style: Option<TransitionStyle>,
+    pub duration: Option<f32>,
+}
+
+/// Blend to another palette, optionally overriding how long it takes
+#[derive(Debug, PartialEq, Clone)]
+pub struct PaletteRequest {
+    pub name: String,
+    pub duration: Option<f32>,
 }
 
 fn parse_packet(packet: &str) -> Option<PdPacket>{
     let mut splits = packet.split_ascii_whitespace();
 
     let path = splits.next()?;
-    let number = splits.next()?.strip_suffix(";")?.parse().ok()?;
+    let args: Vec<&str> = splits.collect();
+    let (last, args) = args.split_last()?;
+    let last = last.strip_suffix(";")?;
 
-    match path {
-        _ if path.starts_with("/voicelevel") => {
-            Some(PdPacket::VoiceLevel(number))
+    match (path, args) {
+        (_, []) if path.starts_with("/voicelevel") => {
+            Some(PdPacket::VoiceLevel(last.parse().ok()?))
+        },
+        _ if path.starts_with("/effect") => {
+            let names = args.iter().copied().chain([last]).map(String::from).collect();
+            Some(PdPacket::Effect(names))
+        },
+        _ if path.starts_with("/expr") => {
+            let source = args.iter().copied().chain([last]).collect::<Vec<_>>().join(" ");
+            Some(PdPacket::Effect(vec![format!("expr:{source}")]))
+        },
+        _ if path.starts_with("/text") => {
+            let text = args.iter().copied().chain([last]).collect::<Vec<_>>().join(" ");
+            Some(PdPacket::Param { effect: "text".into(), param: "text".into(), value: ParamValue::Text(text) })
+        },
+        (_, []) if path.starts_with("/mouth") => {
+            Some(PdPacket::Param { effect: "mouth".into(), param: "shape".into(), value: ParamValue::Text(last.into()) })
+        },
+        (_, [name]) if path.starts_with("/param") => {
+            let (effect, param) = name.split_once('.')?;
+            Some(PdPacket::Param { effect: effect.into(), param: param.into(), value: ParamValue::parse(last) })
+        },
+        _ if path.starts_with("/scene") => {
+            let mut args = args.iter().copied().chain([last]);
+            let name = args.next()?.into();
+
+            let mut request = SceneRequest { name, style: None, duration: None };
+
+            for arg in args {
+                match (TransitionStyle::parse(arg), arg.parse()) {
+                    (Some(style), _) => request.style = Some(style),
+                    (_, Ok(duration)) => request.duration = Some(duration),
+                    _ => return None,
+                }
+            }
+
+            Some(PdPacket::Scene(request))
+        },
+        (_, []) if path.starts_with("/palette") => {
+            Some(PdPacket::Palette(PaletteRequest { name: last.into(), duration: None }))
+        },
+        (_, [name]) if path.starts_with("/palette") => {
+            Some(PdPacket::Palette(PaletteRequest { name: (*name).into(), duration: Some(last.parse().ok()?) }))
         },
         _ => None
     }
 }
 
 pub struct PdState {
-    pub voice_level: f32
+    pub voice_level: f32,
+    /// How many valid packets arrived, each pick below keeps the number of the packet it came
+    /// in, so picking the same thing again can be told apart from no new pick
+    pub message_count: u64,
+    /// The last effects that were picked, if any
+    pub effect: Option<(u64, Vec<String>)>,
+    /// Latest value of each (effect, parameter)
+    pub params: HashMap<(String, String), (u64, ParamValue)>,
+    /// The last scene that was picked, if any
+    pub scene: Option<(u64, SceneRequest)>,
+    /// The last palette that was picked, if any
+    pub palette: Option<(u64, PaletteRequest)>,
+    /// When the last valid packet arrived
+    pub last_message: Option<Instant>,
 }
 
 pub fn receive() -> RLock<PdState> {
     let addr: SocketAddr = (Ipv4Addr::UNSPECIFIED, 2000).into();
     let socket = UdpSocket::bind(addr).unwrap();
 
-    let mut buf = [0u8; 128];
+    // big enough for an expression
+    let mut buf = [0u8; 1024];
 
     // let (pd_tx, pd_rx) = sync_channel(0);
 
-    let (rw_state, r_state) = split_arwlock(PdState {voice_level: 0.0 });
+    let (rw_state, r_state) = split_arwlock(PdState {voice_level: 0.0, message_count: 0, effect: None, params: HashMap::new(), scene: None, palette: None, last_message: None });
 
     std::thread::spawn(move || {
         loop {
@@ -46,10 +123,25 @@ pub fn receive() -> RLock<PdState> {
                     // println!("try_send:\t{data:?}");
                     // let resp = pd_tx.try_send(data.clone());
                     let mut state = rw_state.write().unwrap();
+                    state.last_message = Some(Instant::now());
+                    state.message_count += 1;
+                    let number = state.message_count;
                     match data {
                         PdPacket::VoiceLevel(voice_level) => {
                             state.voice_level = *voice_level
                         },
+                        PdPacket::Effect(names) => {
+                            state.effect = Some((number, names.clone()))
+                        },
+                        PdPacket::Param { effect, param, value } => {
+                            state.params.insert((effect.clone(), param.clone()), (number, value.clone()));
+                        },
+                        PdPacket::Scene(request) => {
+                            state.scene = Some((number, request.clone()))
+                        },
+                        PdPacket::Palette(request) => {
+                            state.palette = Some((number, request.clone()))
+                        },
                     }
                     // .voice_level = data;
 
@@ -68,7 +160,8 @@ pub fn receive() -> RLock<PdState> {
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_packet, PdPacket};
+    use super::{parse_packet, PaletteRequest, PdPacket, SceneRequest};
+    use crate::{effect::ParamValue, scene::TransitionStyle};
 
     #[test]
     fn voicelevel() {
@@ -76,6 +169,53 @@ mod tests {
         assert_eq!(parse_packet("/voicelevel/ 0.0;"), Some(PdPacket::VoiceLevel(0.0)));
         assert_eq!(parse_packet("/voicelevel 10.0;"), Some(PdPacket::VoiceLevel(10.0)));
     }
+
+    #[test]
+    fn effects() {
+        assert_eq!(parse_packet("/effect blobs noise_glow;"), Some(PdPacket::Effect(vec!["blobs".into(), "noise_glow".into()])));
+        assert_eq!(
+            parse_packet("/param blobs.speed 0.5;"),
+            Some(PdPacket::Param { effect: "blobs".into(), param: "speed".into(), value: ParamValue::Number(0.5) })
+        );
+        assert_eq!(
+            parse_packet("/param logo.fit cover;"),
+            Some(PdPacket::Param { effect: "logo".into(), param: "fit".into(), value: ParamValue::Text("cover".into()) })
+        );
+        assert_eq!(parse_packet("/param speed 0.5;"), None);
+
+        assert_eq!(
+            parse_packet("/text hello world;"),
+            Some(PdPacket::Param { effect: "text".into(), param: "text".into(), value: ParamValue::Text("hello world".into()) })
+        );
+        assert_eq!(
+            parse_packet("/mouth o;"),
+            Some(PdPacket::Param { effect: "mouth".into(), param: "shape".into(), value: ParamValue::Text("o".into()) })
+        );
+
+        assert_eq!(
+            parse_packet("/expr hsv(x * 0.1, 1, 0.5);"),
+            Some(PdPacket::Effect(vec!["expr:hsv(x * 0.1, 1, 0.5)".into()]))
+        );
+
+        assert_eq!(
+            parse_packet("/scene angry dissolve 0.3;"),
+            Some(PdPacket::Scene(SceneRequest { name: "angry".into(), style: Some(TransitionStyle::Dissolve), duration: Some(0.3) }))
+        );
+        assert_eq!(
+            parse_packet("/scene sleep;"),
+            Some(PdPacket::Scene(SceneRequest { name: "sleep".into(), style: None, duration: None }))
+        );
+        assert_eq!(parse_packet("/scene sleep slowly;"), None);
+
+        assert_eq!(
+            parse_packet("/palette fire 5;"),
+            Some(PdPacket::Palette(PaletteRequest { name: "fire".into(), duration: Some(5.0) }))
+        );
+        assert_eq!(
+            parse_packet("/palette ocean;"),
+            Some(PdPacket::Palette(PaletteRequest { name: "ocean".into(), duration: None }))
+        );
+    }
     
     #[test]
     fn none(){
@@ -0,0 +1,66 @@
+use std::collections::BTreeSet;
+
+use crate::mapping::LedIndex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelState {
+    Active,
+    /// Wired up but broken, its channels are always sent black
+    Dead,
+    /// Missing entirely (cut off or bridged over), it has no dmx channels
+    Skipped,
+}
+
+/// Pixels of a fixture that don't behave like the rest.
+/// Indices are mapping indices, so they still line up with the positions from the mapping.
+#[derive(Debug, Clone, Default)]
+pub struct PixelMask {
+    pub skipped: BTreeSet<LedIndex>,
+    pub dead: BTreeSet<LedIndex>,
+}
+
+impl PixelMask {
+    pub fn state(&self, index: LedIndex) -> PixelState {
+        if self.skipped.contains(&index) {
+            PixelState::Skipped
+        } else if self.dead.contains(&index) {
+            PixelState::Dead
+        } else {
+            PixelState::Active
+        }
+    }
+
+    /// Index of the pixel in the dmx chain, None if it has no channels
+    pub fn dmx_index(&self, index: LedIndex) -> Option<LedIndex> {
+        if self.skipped.contains(&index) {
+            None
+        } else {
+            Some(index - self.skipped.range(..index).count())
+        }
+    }
+
+    /// Number of pixels that take up dmx channels
+    pub fn num_dmx_pixels(&self, num_pixels: usize) -> usize {
+        num_pixels - self.skipped.range(..num_pixels).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PixelMask, PixelState};
+
+    #[test]
+    fn dmx_index() {
+        let mask = PixelMask {
+            skipped: [2, 3].into(),
+            dead: [5].into(),
+        };
+
+        assert_eq!(mask.dmx_index(1), Some(1));
+        assert_eq!(mask.dmx_index(2), None);
+        assert_eq!(mask.dmx_index(4), Some(2));
+        assert_eq!(mask.dmx_index(5), Some(3));
+        assert_eq!(mask.state(5), PixelState::Dead);
+        assert_eq!(mask.num_dmx_pixels(10), 8);
+    }
+}
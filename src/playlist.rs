@@ -0,0 +1,160 @@
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaylistEntry {
+    pub scene: String,
+    /// In seconds
+    pub duration: f32,
+}
+
+fn default_idle_after() -> f32 {
+    30.0
+}
+
+fn default_voice_threshold() -> f32 {
+    0.01
+}
+
+/// Scenes to cycle through while nobody is talking, for unattended installations
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaylistConfig {
+    /// Seconds without voice activity before the playlist starts
+    #[serde(default = "default_idle_after")]
+    pub idle_after: f32,
+    /// Voice levels above this count as activity
+    #[serde(default = "default_voice_threshold")]
+    pub voice_threshold: f32,
+    /// The scene to go back to when the voice returns, the start scene if not set
+    pub reactive_scene: Option<String>,
+    #[serde(default)]
+    pub shuffle: bool,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+/// Picks the scene to show, based on whether there has been voice activity lately
+pub struct Playlist {
+    config: PlaylistConfig,
+    reactive_scene: String,
+    /// Order the entries are played in
+    order: Vec<usize>,
+    position: usize,
+    /// When the current entry started, None while reactive
+    entry_started: Option<f32>,
+    last_activity: f32,
+}
+
+impl Playlist {
+    pub fn new(config: PlaylistConfig, start_scene: &str) -> Self {
+        let order = (0..config.entries.len()).collect();
+        let reactive_scene = config.reactive_scene.clone().unwrap_or_else(|| start_scene.to_owned());
+
+        Self {
+            config,
+            reactive_scene,
+            order,
+            position: 0,
+            entry_started: None,
+            last_activity: 0.0,
+        }
+    }
+
+    /// Every scene the playlist can switch to
+    pub fn scenes(&self) -> impl Iterator<Item=&str> {
+        std::iter::once(self.reactive_scene.as_str())
+            .chain(self.config.entries.iter().map(|entry| entry.scene.as_str()))
+    }
+
+    fn current_entry(&self) -> &PlaylistEntry {
+        &self.config.entries[self.order[self.position]]
+    }
+
+    fn reshuffle(&mut self) {
+        if self.config.shuffle {
+            let last = self.order.last().copied();
+            self.order.shuffle(&mut rand::thread_rng());
+
+            // don't play the same entry twice in a row
+            if self.order.len() > 1 && self.order.first().copied() == last {
+                self.order.swap(0, 1);
+            }
+        }
+    }
+
+    /// Called every frame with the newest voice level and how long ago pd sent anything.
+    /// Returns the scene to switch to, if it changed.
+    pub fn update(&mut self, voice_level: f32, since_pd_message: f32, elapsed_seconds: f32) -> Option<&str> {
+        // a stale voice level doesn't count once pd has gone quiet
+        if voice_level > self.config.voice_threshold && since_pd_message < self.config.idle_after {
+            self.last_activity = elapsed_seconds;
+        }
+
+        let idle = elapsed_seconds - self.last_activity >= self.config.idle_after;
+
+        if self.config.entries.is_empty() {
+            return None;
+        }
+
+        match (idle, self.entry_started) {
+            (false, None) => None,
+            (false, Some(_)) => {
+                self.entry_started = None;
+                Some(&self.reactive_scene)
+            },
+            (true, None) => {
+                if self.position == 0 {
+                    self.reshuffle();
+                }
+
+                self.entry_started = Some(elapsed_seconds);
+                Some(&self.current_entry().scene)
+            },
+            (true, Some(started)) => {
+                if elapsed_seconds - started < self.current_entry().duration {
+                    return None;
+                }
+
+                self.position = (self.position + 1) % self.order.len();
+                if self.position == 0 {
+                    self.reshuffle();
+                }
+
+                self.entry_started = Some(elapsed_seconds);
+                Some(&self.current_entry().scene)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Playlist, PlaylistConfig, PlaylistEntry};
+
+    #[test]
+    fn cycle() {
+        let entry = |scene: &str, duration| PlaylistEntry { scene: scene.into(), duration };
+
+        let mut playlist = Playlist::new(PlaylistConfig {
+            idle_after: 10.0,
+            voice_threshold: 0.01,
+            reactive_scene: None,
+            shuffle: false,
+            entries: vec![entry("idle", 5.0), entry("sleep", 20.0)],
+        }, "talking");
+
+        assert_eq!(playlist.update(0.5, 0.0, 0.0), None);
+        assert_eq!(playlist.update(0.0, 0.0, 9.0), None);
+        assert_eq!(playlist.update(0.0, 0.0, 10.0), Some("idle"));
+        assert_eq!(playlist.update(0.0, 0.0, 14.0), None);
+        assert_eq!(playlist.update(0.0, 0.0, 15.0), Some("sleep"));
+        assert_eq!(playlist.update(0.0, 0.0, 35.0), Some("idle"));
+
+        // pd stopped sending, the last voice level is stale
+        assert_eq!(playlist.update(0.5, 12.0, 36.0), None);
+
+        assert_eq!(playlist.update(0.5, 0.0, 37.0), Some("talking"));
+        assert_eq!(playlist.update(0.5, 0.0, 38.0), None);
+    }
+}
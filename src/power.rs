@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::LedMappingInfo;
+
+/// A power supply and the fixtures it feeds
+#[derive(Debug, Clone, Deserialize)]
+pub struct Supply {
+    pub max_ma: f32,
+    /// Fixture names or groups
+    pub fixtures: Vec<String>,
+}
+
+/// Estimates the current draw of each frame and dims the output to stay under budget
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PowerLimiter {
+    /// Current of one color channel at full brightness
+    pub ma_per_channel: f32,
+    /// Applies to every fixture, from 0 to 1 since the output is only ever dimmed
+    #[serde(deserialize_with = "brightness")]
+    pub brightness: f32,
+    /// Extra brightness per fixture name or group, from 0 to 1 as well
+    #[serde(deserialize_with = "fixture_brightness")]
+    pub fixture_brightness: BTreeMap<String, f32>,
+    /// Budget for everything together. Room is left for rounding every channel up to the
+    /// next 8 bit step, which dithering can do.
+    pub max_ma: Option<f32>,
+    pub supplies: BTreeMap<String, Supply>,
+}
+
+fn brightness<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    f32::deserialize(deserializer).map(|brightness| brightness.clamp(0.0, 1.0))
+}
+
+fn fixture_brightness<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, f32>, D::Error> {
+    let mut brightness = BTreeMap::<String, f32>::deserialize(deserializer)?;
+    brightness.values_mut().for_each(|brightness| *brightness = brightness.clamp(0.0, 1.0));
+    Ok(brightness)
+}
+
+impl Default for PowerLimiter {
+    fn default() -> Self {
+        Self {
+            ma_per_channel: 20.0,
+            brightness: 1.0,
+            fixture_brightness: BTreeMap::new(),
+            max_ma: None,
+            supplies: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PowerEstimate {
+    /// What the frame would have drawn without any limiting
+    pub requested_ma: f32,
+    /// What is actually sent
+    pub output_ma: f32,
+    /// Output current per supply
+    pub supplies: Vec<(String, f32)>,
+}
+
+impl Display for PowerEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.2}A of {:.2}A requested", self.output_ma / 1000.0, self.requested_ma / 1000.0)?;
+
+        for (supply, supply_ma) in &self.supplies {
+            write!(f, "\n  {supply}: {:.2}A", supply_ma / 1000.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn matches(fixture: &LedMappingInfo, keys: impl IntoIterator<Item=impl AsRef<str>>) -> bool {
+    keys.into_iter()
+        .any(|key| fixture.name == key.as_ref() || fixture.groups.iter().any(|group| group == key.as_ref()))
+}
+
+impl PowerLimiter {
+    fn current(&self, output: &[[f32; 3]]) -> f32 {
+        output.iter().flatten().sum::<f32>() * self.ma_per_channel
+    }
+
+    /// Most current that quantizing can add, if every channel rounds up by one step
+    fn rounding_headroom(&self, output: &[[f32; 3]]) -> f32 {
+        (output.len() * 3) as f32 * self.ma_per_channel / 255.0
+    }
+
+    /// Scale the output values of each fixture down so every budget is met
+    pub fn limit(&self, fixtures: &[LedMappingInfo], outputs: &mut [Vec<[f32; 3]>]) -> PowerEstimate {
+        let brightness: Vec<f32> = fixtures.iter()
+            .map(|fixture| {
+                self.fixture_brightness.iter()
+                    .filter(|(key, _)| matches(fixture, [key]))
+                    .fold(self.brightness, |brightness, (_, fixture_brightness)| brightness * fixture_brightness)
+            })
+            .collect();
+
+        let requested: Vec<f32> = outputs.iter()
+            .zip(&brightness)
+            .map(|(output, brightness)| self.current(output) * brightness)
+            .collect();
+
+        let requested_ma: f32 = requested.iter().sum();
+        let headroom: Vec<f32> = outputs.iter().map(|output| self.rounding_headroom(output)).collect();
+
+        // what's left of a budget after rounding up, over what was requested
+        let scale_for = |max_ma: f32, requested_ma: f32, headroom_ma: f32| {
+            let budget = (max_ma - headroom_ma).max(0.0);
+            if budget < requested_ma { budget / requested_ma } else { 1.0 }
+        };
+
+        let global_scale = match self.max_ma {
+            Some(max_ma) => scale_for(max_ma, requested_ma, headroom.iter().sum()),
+            None => 1.0,
+        };
+
+        let mut limits = vec![global_scale; fixtures.len()];
+
+        for supply in self.supplies.values() {
+            let members: Vec<usize> = (0..fixtures.len())
+                .filter(|&i| matches(&fixtures[i], &supply.fixtures))
+                .collect();
+
+            let supply_ma: f32 = members.iter().map(|&i| requested[i]).sum();
+            let scale = scale_for(supply.max_ma, supply_ma, members.iter().map(|&i| headroom[i]).sum());
+
+            for i in members {
+                limits[i] = limits[i].min(scale);
+            }
+        }
+
+        for (output, scale) in outputs.iter_mut().zip(brightness.iter().zip(&limits).map(|(b, l)| b * l)) {
+            if scale < 1.0 {
+                for value in output.iter_mut().flatten() {
+                    *value *= scale;
+                }
+            }
+        }
+
+        let output: Vec<f32> = outputs.iter().map(|output| self.current(output)).collect();
+
+        let supplies = self.supplies.iter()
+            .map(|(name, supply)| {
+                let supply_ma = (0..fixtures.len())
+                    .filter(|&i| matches(&fixtures[i], &supply.fixtures))
+                    .map(|i| output[i])
+                    .sum();
+
+                (name.clone(), supply_ma)
+            })
+            .collect();
+
+        PowerEstimate {
+            requested_ma,
+            output_ma: output.iter().sum(),
+            supplies,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PowerLimiter, Supply};
+    use crate::{dither::TemporalDither, strip_mapping::StripMapping, LedMappingInfo};
+
+    #[test]
+    fn supply_budget() {
+        let strip = |name| LedMappingInfo::new(StripMapping::new(10, false).into(), Default::default(), (0, 0).into())
+            .with_name(name);
+        let fixtures = vec![strip("left"), strip("right")];
+
+        let mut limiter = PowerLimiter::default();
+        limiter.supplies.insert("left".into(), Supply { max_ma: 300.0, fixtures: vec!["left".into()] });
+
+        // 10 white pixels draw 600mA
+        let mut outputs = vec![vec![[1.0; 3]; 10], vec![[1.0; 3]; 10]];
+        let estimate = limiter.limit(&fixtures, &mut outputs);
+
+        assert_eq!(estimate.requested_ma, 1200.0);
+        assert!(outputs[0][0][0] < 0.5 && outputs[0][0][0] > 0.49);
+        assert_eq!(outputs[1][0], [1.0; 3]);
+        assert!(estimate.supplies[0].1 <= 300.0);
+        assert!(estimate.to_string().starts_with("0.90A of 1.20A requested\n  left: 0.30A"));
+
+        limiter.max_ma = Some(600.0);
+        let mut outputs = vec![vec![[1.0; 3]; 10], vec![[1.0; 3]; 10]];
+        let estimate = limiter.limit(&fixtures, &mut outputs);
+
+        assert!(estimate.output_ma <= 600.0);
+        assert!(outputs[1][0][0] < 0.5);
+
+        // still within budget after dithering rounds up
+        let mut dither = TemporalDither::new(&fixtures);
+        for _ in 0..8 {
+            let dmx_ma: f32 = (0..2)
+                .flat_map(|i| dither.quantize(i, true, &outputs[i]))
+                .flatten()
+                .map(|value| value as f32 / 255.0 * limiter.ma_per_channel)
+                .sum();
+
+            assert!(dmx_ma <= 600.0, "{dmx_ma}");
+        }
+    }
+
+    #[test]
+    fn brightness_range() {
+        let limiter: PowerLimiter = toml::from_str("
+            brightness = 1.5
+            fixture_brightness = { left = -0.5, right = 0.5 }
+        ").unwrap();
+
+        assert_eq!(limiter.brightness, 1.0);
+        assert_eq!(limiter.fixture_brightness.values().collect::<Vec<_>>(), [&0.0, &0.5]);
+    }
+}
@@ -3,11 +3,11 @@
 use std::{sync::mpsc::Receiver};
 
 // use eframe::App;
-use egui::{Color32, Pos2, Rect, TextureHandle, Ui, Vec2, RichText, ColorImage, TextureOptions, Context, Frame};
+use egui::{Color32, Rgba, Pos2, Rect, Ui, RichText, Frame};
 
 use egui_multiwin::{tracked_window::{TrackedWindow, RedrawResponse, TrackedWindowOptions}, multi_window::{MultiWindow, NewWindowRequest}, glutin::{event_loop, window::WindowBuilder, dpi::{PhysicalSize, LogicalSize, LogicalPosition}, platform::macos::WindowBuilderExtMacOS}};
 
-use crate::{LedMappingInfo, LedFrameInfo, LedData, mapping::{LedMappingTrait, LedMappingEnum}, matrix_mapping::MatrixMapping};
+use crate::{LedMappingInfo, LedFrameInfo, LedData, mapping::LedMappingTrait, pixel_mask::PixelState, spatial_index::{PixelRef, SpatialIndex}, transform::Canvas};
 
 struct InfoWindow{
     info_receiver: Receiver<LedFrameInfo>
@@ -23,11 +23,12 @@ impl TrackedWindow for InfoWindow {
     fn redraw(&mut self, data: &mut Self::Data, egui: &mut egui_multiwin::egui_glow::EguiGlow) -> egui_multiwin::tracked_window::RedrawResponse<Self::Data> {
         let frame_info = self.info_receiver.recv().unwrap();
         let frame_data_text = format!(
-            "target period: {:.2}ms\nlast period: {:.2}ms\nrendering period: {:.2}ms\nlast pd message: {:.2}ms", 
+            "target period: {:.2}ms\nlast period: {:.2}ms\nrendering period: {:.2}ms\nlast pd message: {:.2}ms\npower: {}", 
             frame_info.target_period.as_secs_f32()*1000.0, 
             frame_info.last_period.as_secs_f32()*1000.0, 
             frame_info.rendering_period.as_secs_f32()*1000.0,
-            frame_info.elapsed_since_pd_message.as_secs_f32()*1000.0
+            frame_info.elapsed_since_pd_message.as_secs_f32()*1000.0,
+            frame_info.power
     );
 
         egui.egui_winit.set_pixels_per_point(2.0);
@@ -56,7 +57,7 @@ fn new_window_request<T: TrackedWindow<Data=()> + 'static>(window: T, builder: W
     }
 }
 
-pub fn run_gui(matrices: Vec<LedMappingInfo>, led_frame_data_rx: Receiver<Vec<LedData>>, led_frame_info_rx: Receiver<LedFrameInfo>) {
+pub fn run_gui(matrices: Vec<LedMappingInfo>, canvas: Canvas, led_frame_data_rx: Receiver<Vec<LedData>>, led_frame_info_rx: Receiver<LedFrameInfo>) {
 
     let mut windows = MultiWindow::new();
 
@@ -82,7 +83,7 @@ pub fn run_gui(matrices: Vec<LedMappingInfo>, led_frame_data_rx: Receiver<Vec<Le
                 .with_inner_size(LogicalSize::new(250.0, 100.0))
     ), &event_loop).unwrap();
 
-    let fixture_group = LedFixtureGroup::new(matrices);
+    let fixture_group = LedFixtureGroup::new(matrices, canvas);
     let fixture_group_rect = fixture_group.screen_rect();
     println!("Fixture window of size {fixture_group_rect:#?}");
     
@@ -100,113 +101,102 @@ pub fn run_gui(matrices: Vec<LedMappingInfo>, led_frame_data_rx: Receiver<Vec<Le
     windows.run(event_loop, ());
 }
 
-const NEAREST_IMG_FILTER: TextureOptions = TextureOptions {
-    magnification: egui::TextureFilter::Nearest,
-    minification: egui::TextureFilter::Nearest,
-};
-
 struct LedFixtureGroup {
     matrices: Vec<LedMappingInfo>,
-    textures: Option<Vec<TextureHandle>>
+    canvas: Canvas,
+    index: SpatialIndex,
 }
 
 const SCALE: f32 = 10.0;
 
-fn gen_image_for_mapping(mapping: &LedMappingEnum) -> ColorImage {
-    let size = mapping.get_size();
-    ColorImage::new([size.x as usize, size.y as usize], Color32::WHITE)
-}
-
 impl LedFixtureGroup {
-    fn new(matrices: Vec<LedMappingInfo>) -> Self {
+    fn new(matrices: Vec<LedMappingInfo>, canvas: Canvas) -> Self {
         Self {
+            index: SpatialIndex::new(&matrices, &canvas),
             matrices,
-            textures: None
+            canvas
         }
     }
 
-    fn screen_rect(&self) -> Rect {
-        let rects = self.matrices.iter()
-            .map(|info| {
-                let min = Vec2::new(info.pos_offset.x, info.pos_offset.y);
-                let u_size = info.mapping.get_size();
-                let size = Vec2::new(u_size.x as f32, u_size.y as f32);
-                Rect::from_min_size((min * SCALE).to_pos2(), size*SCALE)
-            });
-
-        // let max = positions.fold(Vec2::INFINITY, Vec2::min) + ;
-        // let min = positions.fold(Vec2::ZERO, Vec2::max);
+    /// Convert a world position (mm) into previs points
+    fn to_screen(&self, world_pos: glam::Vec2) -> Pos2 {
+        let canvas_pos = self.canvas.to_canvas(world_pos) * SCALE;
+        Pos2::new(canvas_pos.x, canvas_pos.y)
+    }
 
-        rects.reduce(Rect::union).unwrap()
+    /// Corners of the fixture in previs points
+    fn outline(&self, info: &LedMappingInfo) -> Vec<Pos2> {
+        info.world_outline()
+            .into_iter()
+            .map(|corner| self.to_screen(corner))
+            .collect()
     }
 
-    fn iter(&self) -> impl Iterator<Item=(&LedMappingInfo, &TextureHandle)> {
-        self.textures.iter().flat_map(|textures| {
-            textures.iter()
-                .zip(self.matrices.iter())
-                .map(|(handle, info)| (info, handle))
-        })
+    /// Radius of a drawn pixel in previs points
+    fn pixel_radius(&self, info: &LedMappingInfo) -> f32 {
+        let pitch = info.mapping.get_pitch() * info.transform.scale.abs();
+        pitch.min_element() / self.canvas.unit_size * SCALE * 0.45
     }
 
-    fn get_textures(&mut self, ctx: &mut Context) -> &mut [TextureHandle] {
-        self.textures.get_or_insert_with(|| {
-            self.matrices
-                .iter()
-                .map(|info| {
-                    ctx.load_texture(format!("img{info:?}"), gen_image_for_mapping(&info.mapping), NEAREST_IMG_FILTER)
-                })
-                .collect()
-        })
+    fn screen_rect(&self) -> Rect {
+        let rects = self.matrices.iter()
+            .map(|info| Rect::from_points(&self.outline(info)));
+
+        rects.reduce(Rect::union).unwrap()
     }
 }
 
-fn draw_screens(ui: &mut Ui, group: &LedFixtureGroup) {
+fn draw_screens(ui: &mut Ui, group: &LedFixtureGroup, frame: &[LedData]) {
     let all_cursor = ui.cursor();
-    // let all_offset = group.screen_rect()
-    let group_offset = group.screen_rect().left_top().to_vec2();
-    // let min_offset = group.re
-    let image_scale = 10.0;
+    let group_offset = all_cursor.left_top().to_vec2() - group.screen_rect().left_top().to_vec2();
+
+    let painter = ui.painter();
+
+    for data in frame {
+        let screen_info = &data.info;
 
-    for (screen_info, texture_handle) in group.iter() {
-        let texture_size = texture_handle.size_vec2();
-        let screen_offset = screen_info.pos_offset;
+        let outline: Vec<Pos2> = group.outline(screen_info)
+            .into_iter()
+            .map(|corner| corner + group_offset)
+            .collect();
 
-        let image_offset_pos =
-            Pos2::new(screen_offset.x * SCALE, screen_offset.y * SCALE)
-                - group_offset
-                + all_cursor.left_top().to_vec2();
-                
-        let image_size = texture_size * image_scale;
+        let radius = group.pixel_radius(screen_info);
 
-        let image_rect = Rect::from_min_size(image_offset_pos, image_size);
+        for (i, pixel) in data.data.iter().enumerate() {
+            let center = group.to_screen(screen_info.world_pos(i)) + group_offset;
+
+            match screen_info.mask.state(i) {
+                PixelState::Active => painter.circle_filled(center, radius, Color32::from(Rgba::from_rgb(pixel.r(), pixel.g(), pixel.b()))),
+                PixelState::Dead => painter.circle_stroke(center, radius, (1.0, Color32::DARK_RED)),
+                PixelState::Skipped => {},
+            }
+        }
 
         let info_text = format!(
-            "u: {}\nc: {}",
-            screen_info.dmx_address.universe, screen_info.dmx_address.channel
+            "{}\nu: {}\nc: {}",
+            screen_info.name, screen_info.dmx_address.universe, screen_info.dmx_address.channel
         );
 
-        egui::Frame::none()
-            .stroke(egui::Stroke {
-                width: 1.0,
-                color: Color32::WHITE,
-            })
-            .show(&mut ui.child_ui(image_rect, *ui.layout()), |ui| {
-                ui.image(texture_handle, ui.available_size());
-            });
+        painter.text(outline[0], egui::Align2::LEFT_TOP, info_text, egui::FontId::proportional(8.0), Color32::WHITE);
 
-        let mut inner_ui = ui.child_ui(image_rect, ui.layout().with_main_align(egui::Align::Min));
-        egui::containers::Frame::none().show(&mut inner_ui, |ui| {
-            // ui.cursor().set
-            // ui.put(image_rect, egui::Image::new(texture_handle, image_size));
+        painter.add(egui::Shape::closed_line(outline, egui::Stroke {
+            width: 1.0,
+            color: Color32::WHITE,
+        }));
+    }
 
-            let _text_rect = Rect::from_center_size(image_rect.center_top(), ui.available_size());
-            let label = egui::Label::new(egui::RichText::new(info_text).color(Color32::WHITE));
+    // show which pixel is under the mouse
+    if let Some(hover_pos) = ui.ctx().pointer_hover_pos() {
+        let canvas_pos = (hover_pos - group_offset).to_vec2() / SCALE;
 
-            // ui.with_layout(layout, add_contents)
+        if let Some((PixelRef { fixture, index }, distance)) = group.index.nearest(glam::Vec2::new(canvas_pos.x, canvas_pos.y)) {
+            if distance < 1.0 {
+                let fixture = &group.matrices[fixture];
+                let hover_text = format!("{} #{index}\n{:?}", fixture.name, fixture.pixel_dmx_address(index));
 
-            ui.add(label);
-            // ui.put(text_rect, label);
-        });
+                painter.text(hover_pos, egui::Align2::LEFT_BOTTOM, hover_text, egui::FontId::proportional(8.0), Color32::YELLOW);
+            }
+        }
     }
 }
 
@@ -223,26 +213,11 @@ impl TrackedWindow for ScreensWindow {
         egui.egui_winit.set_pixels_per_point(2.0);
 
         let new_frame = self.frame_data_receiver.recv().unwrap();
-
-        let textures =  self.fixtures.get_textures(&mut egui.egui_ctx);
-
-        for (data, screen) in new_frame.iter().zip(textures.iter_mut()) {
-            let mut image = gen_image_for_mapping(&data.info.mapping);
-
-            for (i, pixel) in data.data.iter().enumerate() {
-                let pos_i = data.info.mapping.get_pos(i);
-                let pixel_index: usize = pos_i.x as usize + pos_i.y as usize * image.width();
-
-                image.pixels[pixel_index] = Color32::from_rgb(pixel[0], pixel[1], pixel[2]);
-
-            }
-            screen.set(image, NEAREST_IMG_FILTER)
-        }
         
         let _response = egui::CentralPanel::default()
             .frame(Frame::none().fill(Color32::BLACK))
             .show(&egui.egui_ctx, |ui| {
-                draw_screens(ui, &self.fixtures);
+                draw_screens(ui, &self.fixtures, &new_frame);
             });
 
         // egui::Frame::none()
@@ -0,0 +1,274 @@
+use ecolor::Rgba;
+use glam::Vec2;
+use serde::Deserialize;
+
+use crate::{draw::DrawContext, effect::Pixel, groups::GroupEffects, layer::render_stack, mapping::LedMappingTrait, LedMappingInfo};
+
+/// How the next scene replaces the current one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionStyle {
+    /// Every pixel fades at the same time
+    #[default]
+    Fade,
+    /// A soft edge sweeping across the canvas from left to right
+    Wipe,
+    /// Pixels switch over one by one in a random order
+    Dissolve,
+}
+
+impl TransitionStyle {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "fade" => Some(TransitionStyle::Fade),
+            "wipe" => Some(TransitionStyle::Wipe),
+            "dissolve" => Some(TransitionStyle::Dissolve),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Transition {
+    pub style: TransitionStyle,
+    /// In seconds
+    pub duration: f32,
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Self {
+            style: TransitionStyle::Fade,
+            duration: 1.0,
+        }
+    }
+}
+
+/// Which scene is shown first and how scenes are switched by default
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneSettings {
+    pub start: Option<String>,
+    pub transition: Transition,
+}
+
+/// Width of the soft edge of wipes and dissolves, relative to the whole transition
+const EDGE: f32 = 0.1;
+
+/// A stable pseudo random value in 0..1 for a canvas position
+fn position_hash(pos: Vec2) -> f32 {
+    let mut hash = pos.x.to_bits().wrapping_mul(0x9e37_79b9) ^ pos.y.to_bits().wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2c1b_3c6d);
+    hash ^= hash >> 12;
+
+    (hash >> 8) as f32 / (1 << 24) as f32
+}
+
+struct ActiveTransition {
+    to: usize,
+    transition: Transition,
+    start_seconds: f32,
+}
+
+impl ActiveTransition {
+    fn progress(&self, elapsed_seconds: f32) -> f32 {
+        if self.transition.duration <= 0.0 {
+            return 1.0;
+        }
+
+        ((elapsed_seconds - self.start_seconds) / self.transition.duration).clamp(0.0, 1.0)
+    }
+
+    /// How much of the next scene shows on a pixel
+    fn weight(&self, progress: f32, pixel: &Pixel, canvas_x: (f32, f32)) -> f32 {
+        // threshold in 0..1 at which the pixel switches over
+        let threshold = match self.transition.style {
+            TransitionStyle::Fade => return progress,
+            TransitionStyle::Wipe => {
+                let (min_x, max_x) = canvas_x;
+                ((pixel.pos.x - min_x) / (max_x - min_x).max(f32::EPSILON)).clamp(0.0, 1.0)
+            },
+            TransitionStyle::Dissolve => position_hash(pixel.pos),
+        };
+
+        ((progress * (1.0 + EDGE) - threshold) / EDGE).clamp(0.0, 1.0)
+    }
+}
+
+/// Named looks for the show, with transitions between them
+pub struct Scenes {
+    scenes: Vec<(String, GroupEffects)>,
+    current: usize,
+    /// Used when a switch doesn't pick its own transition
+    pub default_transition: Transition,
+    active: Option<ActiveTransition>,
+    /// Starts once the active transition is done
+    queued: Option<ActiveTransition>,
+    /// Left and right edge of the fixtures in canvas space, for wipes
+    canvas_x: (f32, f32),
+}
+
+impl Scenes {
+    /// Starts on the first scene
+    pub fn new(scenes: Vec<(String, GroupEffects)>, default_transition: Transition, canvas_x: (f32, f32)) -> Self {
+        assert!(!scenes.is_empty(), "There has to be at least one scene");
+
+        Self {
+            scenes,
+            current: 0,
+            default_transition,
+            active: None,
+            queued: None,
+            canvas_x,
+        }
+    }
+
+    fn index_of(&self, name: &str) -> Option<usize> {
+        self.scenes.iter().position(|(scene_name, _)| scene_name == name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.scenes.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Show a scene straight away, returns false if there is no such scene
+    pub fn cut_to(&mut self, name: &str) -> bool {
+        let Some(index) = self.index_of(name) else {
+            return false;
+        };
+
+        self.current = index;
+        self.active = None;
+        self.queued = None;
+        true
+    }
+
+    /// Start transitioning to a scene, returns false if there is no such scene.
+    /// A transition that is still running plays out first, then this one starts.
+    pub fn switch(&mut self, name: &str, transition: Option<Transition>, elapsed_seconds: f32) -> bool {
+        let Some(index) = self.index_of(name) else {
+            return false;
+        };
+
+        let next = ActiveTransition {
+            to: index,
+            transition: transition.unwrap_or(self.default_transition),
+            start_seconds: elapsed_seconds,
+        };
+
+        match &self.active {
+            Some(active) => self.queued = (index != active.to).then_some(next),
+            None if index != self.current => self.active = Some(next),
+            None => {},
+        }
+
+        true
+    }
+
+    /// The scene that is being switched to, or the one being shown
+    pub fn current_mut(&mut self) -> &mut GroupEffects {
+        let index = self.queued.as_ref()
+            .or(self.active.as_ref())
+            .map_or(self.current, |active| active.to);
+        &mut self.scenes[index].1
+    }
+
+    /// Ends the active transition once it's done and starts the queued one
+    fn finish_transition(&mut self, elapsed_seconds: f32) {
+        let Some(active) = &self.active else {
+            return;
+        };
+
+        if active.progress(elapsed_seconds) >= 1.0 {
+            self.current = active.to;
+            self.active = self.queued.take()
+                .filter(|queued| queued.to != self.current)
+                .map(|queued| ActiveTransition { start_seconds: elapsed_seconds, ..queued });
+        }
+    }
+
+    pub fn all_mut(&mut self) -> impl Iterator<Item=&mut GroupEffects> {
+        self.scenes.iter_mut().map(|(_, effects)| effects)
+    }
+
+    /// Finish transitions that are done and update the effects that are visible
+    pub fn update(&mut self, ctx: &DrawContext) {
+        self.finish_transition(ctx.elapsed_seconds);
+
+        self.scenes[self.current].1.update(ctx);
+
+        if let Some(active) = &self.active {
+            self.scenes[active.to].1.update(ctx);
+        }
+    }
+
+    /// Composite the visible scenes onto `out`, which is indexed by pixel index
+    pub fn render_fixture(&self, ctx: &DrawContext, fixture: &LedMappingInfo, pixels: &[Pixel], out: &mut [Rgba]) {
+        let current = &self.scenes[self.current].1;
+        render_stack(ctx, fixture, pixels, current.stack_for(fixture), out);
+
+        let Some(active) = &self.active else {
+            return;
+        };
+
+        let mut next = vec![Rgba::TRANSPARENT; fixture.mapping.get_num_pixels()];
+        render_stack(ctx, fixture, pixels, self.scenes[active.to].1.stack_for(fixture), &mut next);
+
+        let progress = active.progress(ctx.elapsed_seconds);
+
+        for pixel in pixels {
+            let i = pixel.index;
+            let weight = active.weight(progress, pixel, self.canvas_x);
+
+            out[i] = out[i] * (1.0 - weight) + next[i] * weight;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec3};
+
+    use super::{ActiveTransition, Scenes, Transition, TransitionStyle};
+    use crate::{effect::Pixel, groups::GroupEffects};
+
+    #[test]
+    fn wipe() {
+        let wipe = ActiveTransition {
+            to: 1,
+            transition: Transition { style: TransitionStyle::Wipe, duration: 2.0 },
+            start_seconds: 10.0,
+        };
+
+        let pixel = |x| Pixel { index: 0, pos: Vec2::new(x, 0.0), pos_3d: Vec3::ZERO };
+        let canvas_x = (-10.0, 10.0);
+
+        let progress = wipe.progress(11.0);
+        assert_eq!(progress, 0.5);
+
+        assert_eq!(wipe.weight(progress, &pixel(-10.0), canvas_x), 1.0);
+        assert_eq!(wipe.weight(progress, &pixel(10.0), canvas_x), 0.0);
+
+        assert_eq!(wipe.weight(wipe.progress(9.0), &pixel(-10.0), canvas_x), 0.0);
+        assert_eq!(wipe.weight(wipe.progress(12.0), &pixel(10.0), canvas_x), 1.0);
+    }
+
+    #[test]
+    fn switch_during_transition() {
+        let scenes = ["a", "b", "c"].into_iter().map(|name| (name.to_owned(), GroupEffects::new(vec![]))).collect();
+        let mut scenes = Scenes::new(scenes, Transition::default(), (0.0, 1.0));
+
+        assert!(scenes.switch("b", None, 0.0));
+        assert!(scenes.switch("c", None, 0.5));
+
+        // the fade to b keeps going instead of jumping to its end
+        scenes.finish_transition(0.6);
+        assert_eq!((scenes.current, scenes.active.as_ref().map(|active| active.to)), (0, Some(1)));
+
+        scenes.finish_transition(1.0);
+        assert_eq!(scenes.current, 1);
+        assert_eq!(scenes.active.as_ref().map(|active| (active.to, active.start_seconds)), Some((2, 1.0)));
+    }
+}
@@ -0,0 +1,291 @@
+use std::{cell::{Cell, RefCell}, fmt::Debug, path::PathBuf, sync::{Arc, RwLock}, time::SystemTime};
+
+use ecolor::{linear_from_gamma, HsvaGamma, Rgba};
+use glam::{Vec2, Vec3};
+use noise::{NoiseFn, Perlin};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST};
+
+use crate::{draw::DrawContext, effect::{Effect, Pixel}, palettes::Palette};
+
+/// Shown on every pixel of a script that doesn't work
+const FALLBACK: Rgba = Rgba::from_rgb(0.05, 0.0, 0.0);
+
+/// How often the script file is checked for changes, in seconds
+const RELOAD_INTERVAL: f32 = 0.5;
+
+/// Stops scripts with endless loops from freezing the render loop. A script that runs
+/// into it is turned off until the file changes, instead of hitting it on every pixel.
+const MAX_OPERATIONS: u64 = 10_000;
+
+/// `palette` is what the script's `palette` function picks colors from
+fn create_engine(palette: Arc<RwLock<Palette>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    engine.register_type_with_name::<Vec2>("Vec2")
+        .register_get("x", |pos: &mut Vec2| pos.x)
+        .register_get("y", |pos: &mut Vec2| pos.y)
+        .register_fn("length", |pos: &mut Vec2| pos.length())
+        .register_fn("vec2", Vec2::new)
+        .register_fn("+", |a: Vec2, b: Vec2| a + b)
+        .register_fn("-", |a: Vec2, b: Vec2| a - b)
+        .register_fn("*", |a: Vec2, b: f32| a * b);
+
+    engine.register_type_with_name::<Vec3>("Vec3")
+        .register_get("x", |pos: &mut Vec3| pos.x)
+        .register_get("y", |pos: &mut Vec3| pos.y)
+        .register_get("z", |pos: &mut Vec3| pos.z)
+        .register_fn("length", |pos: &mut Vec3| pos.length())
+        .register_fn("vec3", Vec3::new)
+        .register_fn("+", |a: Vec3, b: Vec3| a + b)
+        .register_fn("-", |a: Vec3, b: Vec3| a - b)
+        .register_fn("*", |a: Vec3, b: f32| a * b);
+
+    engine.register_type_with_name::<Rgba>("Color")
+        // in gamma space like the color pickers
+        .register_fn("rgb", |r: f32, g: f32, b: f32| Rgba::from_rgb(linear_from_gamma(r), linear_from_gamma(g), linear_from_gamma(b)))
+        .register_fn("hsv", |h: f32, s: f32, v: f32| Rgba::from(HsvaGamma { h, s, v, a: 1.0 }))
+        .register_fn("hsva", |h: f32, s: f32, v: f32, a: f32| Rgba::from(HsvaGamma { h, s, v, a }))
+        // the hue wheel unless another palette is picked
+        .register_fn("palette", {
+            let palette = palette.clone();
+            move |t: f32| palette.read().unwrap().shade(t, 1.0, 1.0, 1.0)
+        })
+        .register_fn("palette", move |t: f32, v: f32| palette.read().unwrap().shade(t, 1.0, v, 1.0))
+        .register_fn("+", |a: Rgba, b: Rgba| a + b)
+        .register_fn("*", |a: Rgba, b: f32| a * b);
+
+    let perlin = Perlin::default();
+    engine.register_fn("noise", move |pos: Vec3| perlin.get(pos.as_dvec3().to_array()) as f32);
+
+    engine
+}
+
+/// An effect written in rhai, reloaded whenever the file changes.
+///
+/// The script runs once per pixel with `pos`, `pos_3d`, `elapsed_seconds`, `audio`
+/// and the parameters in scope, and has to end with a color like `palette(t)`.
+/// A script that fails draws a dim red instead.
+#[derive(Clone)]
+pub struct ScriptEffect {
+    name: String,
+    path: PathBuf,
+    engine: Arc<Engine>,
+    /// None while the script doesn't compile
+    ast: Option<AST>,
+    modified: Option<SystemTime>,
+    last_check: f32,
+    params: Vec<(String, f32)>,
+    /// Starts with the variables that are the same for every pixel of a frame, the
+    /// pixel's own are pushed after them
+    scope: RefCell<Scope<'static>>,
+    frame_variables: usize,
+    /// The palette of this frame, shared with the engine
+    palette: Arc<RwLock<Palette>>,
+    /// The last reported error, so it isn't printed every frame
+    error: RefCell<Option<String>>,
+    /// Set when the script ran into `MAX_OPERATIONS`, cleared by a reload
+    too_slow: Cell<bool>,
+}
+
+impl Debug for ScriptEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScriptEffect")
+            .field("path", &self.path)
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+impl ScriptEffect {
+    /// Fails if the file doesn't exist, a script that doesn't compile draws the fallback
+    /// until it's fixed
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        if !path.is_file() {
+            return Err(format!("{path:?}, the file does not exist"));
+        }
+
+        let name = path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let palette = Arc::new(RwLock::new(Palette::default()));
+
+        let mut effect = Self {
+            name,
+            path,
+            engine: Arc::new(create_engine(palette.clone())),
+            ast: None,
+            modified: None,
+            last_check: 0.0,
+            params: vec![],
+            scope: RefCell::new(Scope::new()),
+            frame_variables: 0,
+            palette,
+            error: RefCell::new(None),
+            too_slow: Cell::new(false),
+        };
+
+        effect.reload();
+        Ok(effect)
+    }
+
+    fn modified_time(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    fn reload(&mut self) {
+        self.modified = self.modified_time();
+        self.too_slow.set(false);
+
+        match self.engine.compile_file(self.path.clone()) {
+            Ok(ast) => {
+                self.ast = Some(ast);
+                self.error.replace(None);
+            },
+            Err(err) => {
+                self.ast = None;
+                self.report(err.to_string());
+            },
+        }
+    }
+
+    fn report(&self, error: String) {
+        if self.error.borrow().as_ref() != Some(&error) {
+            eprintln!("The script {:?} failed, drawing the fallback instead\n{error}", self.path);
+            self.error.replace(Some(error));
+        }
+    }
+}
+
+impl Effect for ScriptEffect {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        if (ctx.elapsed_seconds - self.last_check).abs() >= RELOAD_INTERVAL {
+            self.last_check = ctx.elapsed_seconds;
+
+            if self.modified_time() != self.modified {
+                self.reload();
+            }
+        }
+
+        if *self.palette.read().unwrap() != *ctx.palette {
+            *self.palette.write().unwrap() = ctx.palette.clone();
+        }
+
+        let audio: Array = ctx.audio.iter().map(|&sample| Dynamic::from_float(sample)).collect();
+
+        let scope = self.scope.get_mut();
+        scope.clear();
+        scope.push_constant("elapsed_seconds", ctx.elapsed_seconds);
+        scope.push_constant("audio", audio);
+
+        for (name, value) in &self.params {
+            scope.push_constant(name.clone(), *value);
+        }
+
+        self.frame_variables = scope.len();
+    }
+
+    fn render_pixel(&self, _ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        let Some(ast) = self.ast.as_ref().filter(|_| !self.too_slow.get()) else {
+            return FALLBACK;
+        };
+
+        let mut scope = self.scope.borrow_mut();
+        scope.rewind(self.frame_variables);
+        scope.push_constant("pos", pixel.pos);
+        scope.push_constant("pos_3d", pixel.pos_3d);
+
+        match self.engine.eval_ast_with_scope::<Dynamic>(&mut scope, ast) {
+            Ok(result) => result.try_cast::<Rgba>().unwrap_or_else(|| {
+                self.report("The script has to end with a color, like rgb(1.0, 0.5, 0.0)".into());
+                FALLBACK
+            }),
+            Err(err) => {
+                if matches!(*err, EvalAltResult::ErrorTooManyOperations(_)) {
+                    self.too_slow.set(true);
+                    self.report(format!("{err}, it's turned off until the file is saved again"));
+                } else {
+                    self.report(err.to_string());
+                }
+                FALLBACK
+            },
+        }
+    }
+
+    /// Any name can be a parameter, they are passed to the script as variables
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match self.params.iter_mut().find(|(param, _)| param == name) {
+            Some((_, param_value)) => *param_value = value,
+            None => self.params.push((name.to_owned(), value)),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Rgba;
+    use glam::{Vec2, Vec3};
+
+    use super::{ScriptEffect, FALLBACK};
+    use crate::{draw::{test_context, DrawContext}, effect::{Effect, Pixel}, palettes::Palette};
+
+    #[test]
+    fn script() {
+        let path = std::env::temp_dir().join("rpi_led_matrix_test_pulse.rhai");
+        std::fs::write(&path, "let v = pos.x * brightness; rgb(v, v, v)").unwrap();
+
+        let ctx = DrawContext { bounds: (Vec2::ZERO, Vec2::ONE), ..test_context(0.0) };
+        let pixel = Pixel { index: 0, pos: Vec2::new(1.0, 0.0), pos_3d: Vec3::ZERO };
+
+        let mut effect = ScriptEffect::load(path.clone()).unwrap();
+        assert_eq!(effect.name(), "rpi_led_matrix_test_pulse");
+
+        effect.set_param("brightness", 1.0);
+        effect.update(&ctx);
+        assert_eq!(effect.render_pixel(&ctx, &pixel), Rgba::from_rgb(1.0, 1.0, 1.0));
+
+        // colors from the palette of the frame
+        let palette = Palette::from_colors(vec![Rgba::BLACK, Rgba::WHITE]).unwrap();
+        let ctx = DrawContext { palette: &palette, ..ctx };
+        std::fs::write(&path, "palette(pos.x * 0.25, 0.5)").unwrap();
+        effect.reload();
+        effect.update(&ctx);
+        assert_eq!(effect.render_pixel(&ctx, &pixel), palette.shade(0.25, 1.0, 0.5, 1.0));
+
+        // a script that fails at runtime
+        std::fs::write(&path, "pos.w").unwrap();
+        effect.reload();
+        effect.update(&ctx);
+        assert_eq!(effect.render_pixel(&ctx, &pixel), FALLBACK);
+
+        // a script that never ends is turned off until it's reloaded
+        std::fs::write(&path, "loop {}").unwrap();
+        effect.reload();
+        effect.update(&ctx);
+        assert_eq!(effect.render_pixel(&ctx, &pixel), FALLBACK);
+        assert!(effect.too_slow.get());
+
+        effect.reload();
+        assert!(!effect.too_slow.get());
+
+        std::fs::remove_file(&path).ok();
+        assert!(ScriptEffect::load(path).is_err());
+    }
+
+    #[test]
+    fn ripple_without_audio() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("effects/ripple.rhai");
+        let ctx = test_context(1.0);
+        let pixel = Pixel { index: 0, pos: Vec2::new(3.0, 1.0), pos_3d: Vec3::ZERO };
+
+        let mut effect = ScriptEffect::load(path).unwrap();
+        effect.update(&ctx);
+        assert_ne!(effect.render_pixel(&ctx, &pixel), FALLBACK);
+    }
+}
@@ -0,0 +1,501 @@
+use ecolor::Rgba;
+use glam::{IVec2, UVec2, Vec2};
+use rand::Rng;
+
+use crate::{draw::DrawContext, effect::{parse_color, Effect, Pixel}};
+
+/// Steps beyond this in one frame are dropped, so a stalled frame doesn't cause a burst
+const MAX_STEPS_PER_FRAME: u32 = 4;
+
+/// Cells covering the area of the fixtures, for effects that simulate something
+#[derive(Debug, Clone, Default)]
+struct Grid<T> {
+    bounds: (Vec2, Vec2),
+    /// In canvas units
+    cell_size: f32,
+    size: UVec2,
+    cells: Vec<T>,
+}
+
+impl<T: Clone + Default> Grid<T> {
+    fn new(bounds: (Vec2, Vec2), cell_size: f32) -> Self {
+        let (min, max) = bounds;
+        let size = ((max - min) / cell_size).ceil().max(Vec2::ONE).as_uvec2();
+
+        Self {
+            bounds,
+            cell_size,
+            size,
+            cells: vec![T::default(); (size.x * size.y) as usize],
+        }
+    }
+
+    /// Starts over with empty cells if the fixtures or cell size changed, returns true if it did
+    fn fit(&mut self, bounds: (Vec2, Vec2), cell_size: f32) -> bool {
+        let cell_size = cell_size.max(0.1);
+
+        if self.bounds == bounds && self.cell_size == cell_size && !self.cells.is_empty() {
+            return false;
+        }
+
+        *self = Self::new(bounds, cell_size);
+        true
+    }
+
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        let inside = cell.cmpge(IVec2::ZERO).all() && cell.cmplt(self.size.as_ivec2()).all();
+        inside.then(|| (cell.y * self.size.x as i32 + cell.x) as usize)
+    }
+
+    /// Cells outside of the grid are taken from the other side
+    fn wrapped(&self, cell: IVec2) -> &T {
+        let size = self.size.as_ivec2();
+        let cell = IVec2::new(cell.x.rem_euclid(size.x), cell.y.rem_euclid(size.y));
+        &self.cells[(cell.y * size.x + cell.x) as usize]
+    }
+
+    /// Cells outside of the grid are taken from the closest edge
+    fn clamped(&self, cell: IVec2) -> &T {
+        let cell = cell.clamp(IVec2::ZERO, self.size.as_ivec2() - 1);
+        &self.cells[(cell.y * self.size.x as i32 + cell.x) as usize]
+    }
+
+    fn cell_at(&self, pos: Vec2) -> Option<&T> {
+        let cell = ((pos - self.bounds.0) / self.cell_size).floor().as_ivec2();
+        self.index(cell).map(|index| &self.cells[index])
+    }
+
+    fn cell_coords(&self) -> impl Iterator<Item=IVec2> {
+        let size = self.size.as_ivec2();
+        (0..size.y).flat_map(move |y| (0..size.x).map(move |x| IVec2::new(x, y)))
+    }
+}
+
+/// Runs a simulation at a fixed rate, independent of the frame rate
+#[derive(Debug, Clone, Default)]
+struct Stepper {
+    last_update: Option<f32>,
+    /// Fraction of a step left over from the last frames
+    pending: f32,
+}
+
+impl Stepper {
+    /// How many steps to run this frame, at `rate` steps per second
+    fn steps(&mut self, elapsed_seconds: f32, rate: f32) -> u32 {
+        let delta = self.last_update.map_or(0.0, |last_update| (elapsed_seconds - last_update).max(0.0));
+        self.last_update = Some(elapsed_seconds);
+
+        self.pending += delta * rate.max(0.0);
+        let steps = self.pending.floor();
+        self.pending -= steps;
+
+        (steps as u32).min(MAX_STEPS_PER_FRAME)
+    }
+}
+
+/// Heat rising from the bottom edge and cooling off on the way up
+#[derive(Debug, Clone)]
+pub struct Fire {
+    heat: Grid<f32>,
+    stepper: Stepper,
+    /// Steps per second
+    speed: f32,
+    /// In canvas units
+    cell: f32,
+    /// Heat lost per step at most
+    cooling: f32,
+    /// Heat of the fuel at the bottom
+    intensity: f32,
+    /// How much the voice level adds to the fuel
+    audio: f32,
+    brightness: f32,
+}
+
+impl Default for Fire {
+    fn default() -> Self {
+        Self {
+            heat: Grid::default(),
+            stepper: Stepper::default(),
+            speed: 30.0,
+            cell: 1.0,
+            cooling: 0.1,
+            intensity: 0.8,
+            audio: 0.0,
+            brightness: 1.0,
+        }
+    }
+}
+
+impl Fire {
+    fn step(&mut self, fuel: f32, rng: &mut impl Rng) {
+        let size = self.heat.size.as_ivec2();
+
+        for x in 0..size.x {
+            self.heat.cells[((size.y - 1) * size.x + x) as usize] = fuel * rng.gen_range(0.6..=1.0);
+        }
+
+        // top down, so every row takes its heat from the row below before that one moves on
+        for y in 0..size.y - 1 {
+            for x in 0..size.x {
+                let below = *self.heat.clamped(IVec2::new(x + rng.gen_range(-1..=1), y + 1));
+                self.heat.cells[(y * size.x + x) as usize] = (below - rng.gen::<f32>() * self.cooling).max(0.0);
+            }
+        }
+    }
+}
+
+impl Effect for Fire {
+    fn name(&self) -> &str {
+        "fire"
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        self.heat.fit(ctx.bounds, self.cell);
+
+        let voice = ctx.audio.first().map_or(0.0, |level| level.abs());
+        let fuel = (self.intensity + voice * self.audio).min(1.0);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..self.stepper.steps(ctx.elapsed_seconds, self.speed) {
+            self.step(fuel, &mut rng);
+        }
+    }
+
+    fn render_pixel(&self, ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        let heat = self.heat.cell_at(pixel.pos).copied().unwrap_or_default();
+
+        // red embers, orange flames and a yellow white core on the hue wheel
+        let h = 0.01 + heat * 0.13;
+        let s = (1.8 - heat * 1.2).min(1.0);
+        let v = (heat * 1.5).min(1.0);
+
+        ctx.palette.shade(h, s, v, 1.0) * self.brightness
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "speed" => self.speed = value,
+            "cell" => self.cell = value,
+            "cooling" => self.cooling = value,
+            "intensity" => self.intensity = value,
+            "audio" => self.audio = value,
+            "brightness" => self.brightness = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Still lifes and oscillators never die out, so the board is seeded again after this many steps
+const MAX_GENERATIONS: u32 = 500;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct LifeCell {
+    alive: bool,
+    /// Fades out after the cell dies
+    glow: f32,
+}
+
+/// Conway's Game of Life, seeded again when it dies out or settles down
+#[derive(Debug, Clone)]
+pub struct Life {
+    cells: Grid<LifeCell>,
+    stepper: Stepper,
+    generation: u32,
+    /// Steps per second
+    speed: f32,
+    /// In canvas units
+    cell: f32,
+    /// Share of cells alive after seeding
+    density: f32,
+    /// Glow kept per step by dead cells
+    trail: f32,
+    color: Rgba,
+    brightness: f32,
+}
+
+impl Default for Life {
+    fn default() -> Self {
+        Self {
+            cells: Grid::default(),
+            stepper: Stepper::default(),
+            generation: 0,
+            speed: 8.0,
+            cell: 1.0,
+            density: 0.3,
+            trail: 0.6,
+            color: Rgba::from_srgba_unmultiplied(80, 160, 255, 255),
+            brightness: 1.0,
+        }
+    }
+}
+
+impl Life {
+    fn seed(&mut self, rng: &mut impl Rng) {
+        for cell in &mut self.cells.cells {
+            cell.alive = rng.gen::<f32>() < self.density;
+        }
+        self.generation = 0;
+    }
+
+    /// Returns false if nothing changed
+    fn step(&mut self) -> bool {
+        let next: Vec<_> = self.cells.cell_coords()
+            .map(|cell| {
+                let neighbors = (-1..=1).flat_map(|y| (-1..=1).map(move |x| IVec2::new(x, y)))
+                    .filter(|offset| *offset != IVec2::ZERO && self.cells.wrapped(cell + *offset).alive)
+                    .count();
+
+                let current = self.cells.wrapped(cell);
+                let alive = matches!((current.alive, neighbors), (true, 2) | (_, 3));
+
+                LifeCell {
+                    alive,
+                    glow: if alive { 1.0 } else { current.glow * self.trail },
+                }
+            })
+            .collect();
+
+        let changed = next.iter().zip(&self.cells.cells).any(|(next, current)| next.alive != current.alive);
+
+        self.cells.cells = next;
+        self.generation += 1;
+        changed
+    }
+}
+
+impl Effect for Life {
+    fn name(&self) -> &str {
+        "life"
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        let mut rng = rand::thread_rng();
+
+        if self.cells.fit(ctx.bounds, self.cell) {
+            self.seed(&mut rng);
+        }
+
+        for _ in 0..self.stepper.steps(ctx.elapsed_seconds, self.speed) {
+            if !self.step() || self.generation >= MAX_GENERATIONS {
+                self.seed(&mut rng);
+            }
+        }
+    }
+
+    fn render_pixel(&self, _ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        let glow = self.cells.cell_at(pixel.pos).map_or(0.0, |cell| cell.glow);
+        self.color * glow * self.brightness
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "speed" => self.speed = value,
+            "cell" => self.cell = value,
+            "density" => self.density = value,
+            "trail" => self.trail = value,
+            "brightness" => self.brightness = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_text_param(&mut self, name: &str, value: &str) -> bool {
+        match (name, parse_color(value)) {
+            ("color", Some(color)) => self.color = color,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct RainDrop {
+    /// In cells from the top, negative while waiting to fall
+    y: f32,
+    /// Cells per step
+    speed: f32,
+}
+
+/// Falling streaks like the digital rain in The Matrix, one drop per column
+#[derive(Debug, Clone)]
+pub struct Rain {
+    glow: Grid<f32>,
+    drops: Vec<RainDrop>,
+    stepper: Stepper,
+    /// Steps per second
+    speed: f32,
+    /// In canvas units
+    cell: f32,
+    /// How often drops fall, 1 keeps every column busy
+    density: f32,
+    /// Glow kept per step behind the drops
+    trail: f32,
+    color: Rgba,
+    brightness: f32,
+}
+
+impl Default for Rain {
+    fn default() -> Self {
+        Self {
+            glow: Grid::default(),
+            drops: vec![],
+            stepper: Stepper::default(),
+            speed: 20.0,
+            cell: 1.0,
+            density: 0.5,
+            trail: 0.85,
+            color: Rgba::from_srgba_unmultiplied(40, 255, 90, 255),
+            brightness: 1.0,
+        }
+    }
+}
+
+impl Rain {
+    /// Starts a drop above the top edge, waiting longer the lower the density
+    fn respawn(&self, rng: &mut impl Rng) -> RainDrop {
+        let height = self.glow.size.y as f32;
+        let wait = height / self.density.max(0.01) - height;
+
+        RainDrop {
+            y: -rng.gen_range(0.0..=wait.max(0.0)) - 1.0,
+            speed: rng.gen_range(0.3..=1.0),
+        }
+    }
+
+    fn step(&mut self, rng: &mut impl Rng) {
+        for glow in &mut self.glow.cells {
+            *glow *= self.trail;
+        }
+
+        for x in 0..self.drops.len() {
+            let drop = &mut self.drops[x];
+            drop.y += drop.speed;
+
+            if drop.y >= self.glow.size.y as f32 {
+                self.drops[x] = self.respawn(rng);
+            } else if let Some(index) = self.glow.index(IVec2::new(x as i32, drop.y.floor() as i32)) {
+                self.glow.cells[index] = 1.0;
+            }
+        }
+    }
+}
+
+impl Effect for Rain {
+    fn name(&self) -> &str {
+        "rain"
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        let mut rng = rand::thread_rng();
+
+        if self.glow.fit(ctx.bounds, self.cell) {
+            self.drops = (0..self.glow.size.x).map(|_| self.respawn(&mut rng)).collect();
+        }
+
+        for _ in 0..self.stepper.steps(ctx.elapsed_seconds, self.speed) {
+            self.step(&mut rng);
+        }
+    }
+
+    fn render_pixel(&self, _ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        let glow = self.glow.cell_at(pixel.pos).copied().unwrap_or_default();
+
+        // the head of a drop is brighter than its trail
+        let color = if glow >= 1.0 { self.color * 0.4 + Rgba::WHITE * 0.6 } else { self.color * glow };
+        color * self.brightness
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "speed" => self.speed = value,
+            "cell" => self.cell = value,
+            "density" => self.density = value,
+            "trail" => self.trail = value,
+            "brightness" => self.brightness = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_text_param(&mut self, name: &str, value: &str) -> bool {
+        match (name, parse_color(value)) {
+            ("color", Some(color)) => self.color = color,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, Vec2};
+
+    use super::{Fire, Grid, Life, LifeCell, Rain, RainDrop, Stepper};
+
+    #[test]
+    fn life() {
+        let mut life = Life { cells: Grid::new((Vec2::ZERO, Vec2::splat(5.0)), 1.0), ..Default::default() };
+
+        // a blinker, three cells in a column turn into a row and back
+        let alive = |life: &Life| life.cells.cell_coords().filter(|cell| life.cells.wrapped(*cell).alive).collect::<Vec<_>>();
+        for y in 1..=3 {
+            life.cells.cells[y * 5 + 2] = LifeCell { alive: true, glow: 1.0 };
+        }
+
+        assert!(life.step());
+        assert_eq!(alive(&life), [IVec2::new(1, 2), IVec2::new(2, 2), IVec2::new(3, 2)]);
+        assert_eq!(life.cells.cell_at(Vec2::new(2.5, 1.5)).unwrap().glow, life.trail);
+
+        assert!(life.step());
+        assert_eq!(alive(&life), [IVec2::new(2, 1), IVec2::new(2, 2), IVec2::new(2, 3)]);
+
+        // a fixed number of steps per second, whatever the frame rate
+        let mut stepper = Stepper::default();
+        let steps: u32 = [0.0, 0.25, 0.5, 0.75, 1.0].iter().map(|seconds| stepper.steps(*seconds, 2.0)).sum();
+        assert_eq!(steps, 2);
+    }
+
+    #[test]
+    fn fire() {
+        let mut fire = Fire { heat: Grid::new((Vec2::ZERO, Vec2::new(3.0, 4.0)), 1.0), cooling: 0.0, ..Default::default() };
+        let mut rng = rand::thread_rng();
+
+        // the fuel lights the bottom row and the heat climbs one row per step
+        fire.step(1.0, &mut rng);
+        assert!(fire.heat.cells[9..].iter().all(|heat| (0.6..=1.0).contains(heat)));
+        assert!(fire.heat.cells[..6].iter().all(|heat| *heat == 0.0));
+
+        for _ in 0..3 {
+            fire.step(1.0, &mut rng);
+        }
+        assert!(fire.heat.cells.iter().all(|heat| (0.6..=1.0).contains(heat)));
+
+        // and burns out without fuel
+        for _ in 0..4 {
+            fire.step(0.0, &mut rng);
+        }
+        assert!(fire.heat.cells.iter().all(|heat| *heat == 0.0));
+    }
+
+    #[test]
+    fn rain() {
+        let mut rain = Rain { glow: Grid::new((Vec2::ZERO, Vec2::new(2.0, 3.0)), 1.0), ..Default::default() };
+        rain.drops = vec![RainDrop { y: -1.0, speed: 1.0 }, RainDrop { y: -3.0, speed: 1.0 }];
+        let mut rng = rand::thread_rng();
+
+        // the first drop lights the top cell, the second is still waiting
+        rain.step(&mut rng);
+        assert_eq!(rain.glow.cells, [1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        // and leaves a fading trail behind
+        rain.step(&mut rng);
+        assert_eq!(rain.glow.cells, [rain.trail, 0.0, 1.0, 0.0, 0.0, 0.0]);
+
+        // a drop past the bottom starts over above the top
+        rain.step(&mut rng);
+        rain.step(&mut rng);
+        assert!(rain.drops[0].y < 0.0);
+        assert_eq!(rain.glow.index(IVec2::new(1, 0)).map(|index| rain.glow.cells[index]), Some(rain.trail));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use glam::{IVec2, Vec2};
+
+use crate::{mapping::{LedIndex, LedMappingTrait}, pixel_mask::PixelState, transform::Canvas, LedMappingInfo};
+
+/// A pixel of a specific fixture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PixelRef {
+    /// Index into the fixture list the index was built from
+    pub fixture: usize,
+    pub index: LedIndex,
+}
+
+/// Finds the fixture pixels at a canvas position, the inverse of `get_pos`.
+/// Pixels are bucketed into a grid of square cells.
+pub struct SpatialIndex {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<(PixelRef, Vec2)>>,
+    /// Lowest and highest cell with pixels in it, empty ranges if there are none
+    occupied: (IVec2, IVec2),
+}
+
+impl SpatialIndex {
+    /// Index every pixel with channels in canvas space, with cells one canvas unit wide
+    pub fn new(fixtures: &[LedMappingInfo], canvas: &Canvas) -> Self {
+        let mut index = Self {
+            cell_size: 1.0,
+            cells: HashMap::new(),
+            occupied: (IVec2::ZERO, IVec2::NEG_ONE),
+        };
+
+        for (fixture_index, fixture) in fixtures.iter().enumerate() {
+            for i in 0..fixture.mapping.get_num_pixels() {
+                if fixture.mask.state(i) == PixelState::Skipped {
+                    continue;
+                }
+
+                let pos = canvas.to_canvas(fixture.world_pos(i));
+                let pixel = PixelRef { fixture: fixture_index, index: i };
+
+                index.cells.entry(index.cell(pos)).or_default().push((pixel, pos));
+            }
+        }
+
+        if let Some(occupied) = index.cells.keys().map(|&cell| (cell, cell)).reduce(|(low, high), (cell, _)| (low.min(cell), high.max(cell))) {
+            index.occupied = occupied;
+        }
+
+        index
+    }
+
+    fn cell(&self, pos: Vec2) -> IVec2 {
+        (pos / self.cell_size).floor().as_ivec2()
+    }
+
+    /// All the pixels in the cells between min and max
+    fn pixels_in_cells(&self, min: Vec2, max: Vec2) -> impl Iterator<Item=&(PixelRef, Vec2)> {
+        let (low, high) = self.occupied;
+        let min = self.cell(min).max(low);
+        let max = self.cell(max).min(high);
+
+        (min.y..=max.y)
+            .flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+    }
+
+    /// The cells on the edge of the square `ring` cells out from center that can have pixels in them
+    fn ring_cells(&self, center: IVec2, ring: i32) -> impl Iterator<Item=IVec2> {
+        let (low, high) = self.occupied;
+        let (min, max) = (center - ring, center + ring);
+        let sides = if ring == 0 { 1 } else { 2 };
+
+        let xs = min.x.max(low.x)..=max.x.min(high.x);
+        // the corners are part of the rows already
+        let ys = (min.y + 1).max(low.y)..=(max.y - 1).min(high.y);
+
+        let rows = [min.y, max.y].into_iter().take(sides).filter(move |y| (low.y..=high.y).contains(y));
+        let columns = [min.x, max.x].into_iter().take(sides).filter(move |x| (low.x..=high.x).contains(x));
+
+        rows.flat_map(move |y| xs.clone().map(move |x| IVec2::new(x, y)))
+            .chain(columns.flat_map(move |x| ys.clone().map(move |y| IVec2::new(x, y))))
+    }
+
+    /// The closest pixel to pos and its distance
+    pub fn nearest(&self, pos: Vec2) -> Option<(PixelRef, f32)> {
+        if self.cells.is_empty() {
+            return None;
+        }
+
+        // far enough away that the cell math can't overflow
+        let center = self.cell(pos).clamp(IVec2::splat(-(1 << 29)), IVec2::splat(1 << 29));
+        let mut best: Option<(PixelRef, f32)> = None;
+
+        // rings closer than the occupied cells are empty, and past the farthest one there's nothing left
+        let (low, high) = self.occupied;
+        let first_ring = (low - center).max(center - high).max(IVec2::ZERO).max_element();
+        let last_ring = (high - center).max(center - low).max_element();
+
+        // check rings of cells around the center until nothing closer can be found
+        for ring in first_ring..=last_ring {
+            for cell in self.ring_cells(center, ring) {
+                for (pixel, pixel_pos) in self.cells.get(&cell).into_iter().flatten() {
+                    let distance = pixel_pos.distance(pos);
+
+                    if best.is_none_or(|(_, best_distance)| distance < best_distance) {
+                        best = Some((*pixel, distance));
+                    }
+                }
+            }
+
+            // anything in the next ring is at least this far away
+            let ring_distance = ring as f32 * self.cell_size;
+
+            if let Some((_, best_distance)) = best {
+                if best_distance <= ring_distance {
+                    break;
+                }
+            }
+        }
+
+        best
+    }
+
+    pub fn in_radius(&self, pos: Vec2, radius: f32) -> Vec<PixelRef> {
+        self.pixels_in_cells(pos - Vec2::splat(radius), pos + Vec2::splat(radius))
+            .filter(|(_, pixel_pos)| pixel_pos.distance(pos) <= radius)
+            .map(|(pixel, _)| *pixel)
+            .collect()
+    }
+
+    pub fn in_polygon(&self, polygon: &[Vec2]) -> Vec<PixelRef> {
+        if polygon.is_empty() {
+            return vec![];
+        }
+
+        let min = polygon.iter().copied().reduce(Vec2::min).unwrap();
+        let max = polygon.iter().copied().reduce(Vec2::max).unwrap();
+
+        self.pixels_in_cells(min, max)
+            .filter(|(_, pixel_pos)| polygon_contains(polygon, *pixel_pos))
+            .map(|(pixel, _)| *pixel)
+            .collect()
+    }
+}
+
+/// Even-odd rule, so self intersecting polygons have holes
+fn polygon_contains(polygon: &[Vec2], pos: Vec2) -> bool {
+    let mut inside = false;
+
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+
+        if (a.y > pos.y) != (b.y > pos.y) {
+            let crossing_x = a.x + (pos.y - a.y) / (b.y - a.y) * (b.x - a.x);
+
+            if pos.x < crossing_x {
+                inside = !inside;
+            }
+        }
+    }
+
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::{PixelRef, SpatialIndex};
+    use crate::{strip_mapping::StripMapping, transform::{Canvas, Transform2D}, LedMappingInfo};
+
+    fn strips() -> Vec<LedMappingInfo> {
+        let strip = |y| LedMappingInfo::new(
+            StripMapping::new(10, false).with_pitch(1.0).into(),
+            Transform2D::from_translation(Vec2::new(0.0, y)),
+            (0, 0).into()
+        );
+
+        vec![strip(0.0), strip(5.0)]
+    }
+
+    #[test]
+    fn nearest() {
+        let index = SpatialIndex::new(&strips(), &Canvas::new(Vec2::ZERO, 1.0));
+
+        assert_eq!(index.nearest(Vec2::new(3.4, 0.6)).unwrap().0, PixelRef { fixture: 0, index: 3 });
+        assert_eq!(index.nearest(Vec2::new(20.0, 4.0)).unwrap().0, PixelRef { fixture: 1, index: 9 });
+        assert_eq!(index.nearest(Vec2::new(-100.0, 100.0)).unwrap().0, PixelRef { fixture: 1, index: 0 });
+    }
+
+    #[test]
+    fn far_away() {
+        let index = SpatialIndex::new(&strips(), &Canvas::new(Vec2::ZERO, 1.0));
+
+        // only the rings around the fixtures are searched, so these are as quick as close ones
+        assert_eq!(index.nearest(Vec2::new(3000.0, 0.0)).unwrap().0, PixelRef { fixture: 0, index: 9 });
+        assert_eq!(index.nearest(Vec2::new(-1e12, -1e12)).unwrap().0, PixelRef { fixture: 0, index: 0 });
+        assert_eq!(index.in_radius(Vec2::ZERO, 100_000.0).len(), 20);
+        assert!(index.in_radius(Vec2::new(3000.0, 0.0), 10.0).is_empty());
+    }
+
+    #[test]
+    fn queries() {
+        let index = SpatialIndex::new(&strips(), &Canvas::new(Vec2::ZERO, 1.0));
+
+        let mut in_radius = index.in_radius(Vec2::new(2.5, 0.5), 1.1);
+        in_radius.sort_by_key(|pixel| pixel.index);
+        assert_eq!(in_radius.iter().map(|pixel| pixel.index).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let triangle = [Vec2::new(0.0, 0.0), Vec2::new(10.0, 0.0), Vec2::new(0.0, 10.0)];
+        let in_polygon = index.in_polygon(&triangle);
+        assert_eq!(in_polygon.iter().filter(|pixel| pixel.fixture == 0).count(), 9);
+        assert_eq!(in_polygon.iter().filter(|pixel| pixel.fixture == 1).count(), 4);
+    }
+}
@@ -2,30 +2,35 @@ use glam::{Vec2, UVec2};
 
 use crate::mapping::*;
 
+/// Pixel pitch of a 60 leds/m strip
+pub const DEFAULT_STRIP_PITCH: f32 = 1000.0 / 60.0;
+
 #[derive(Debug, Clone, Copy)]
 pub struct StripMapping {
     length: LedIndex,
     inverted: bool,
+    pitch: f32,
 }
 
 impl StripMapping {
     pub fn new(length: LedIndex, inverted: bool) -> Self {
-        Self { length, inverted }
+        Self { length, inverted, pitch: DEFAULT_STRIP_PITCH }
+    }
+
+    pub fn with_pitch(self, pitch: f32) -> Self {
+        Self { pitch, ..self }
     }
 }
 
 impl Default for StripMapping {
     fn default() -> Self {
-        Self {
-            length: 16,
-            inverted: false
-        }
+        Self::new(16, false)
     }
 }
 
 //todo: probably separate these concerns
 impl LedMappingTrait for StripMapping {
-    fn get_pos(&self, index: LedIndex) -> UPos {
+    fn get_grid_pos(&self, index: LedIndex) -> UPos {
         let mut x = index % self.length;
 
         x = if self.inverted {
@@ -37,6 +42,10 @@ impl LedMappingTrait for StripMapping {
         [x as u32, 0].into()
     }
 
+    fn get_pitch(&self) -> Vec2 {
+        Vec2::splat(self.pitch)
+    }
+
     fn get_size(&self) -> UVec2 {
         UVec2::new(self.length as u32, 1)
     }
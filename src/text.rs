@@ -0,0 +1,271 @@
+use std::convert::Infallible;
+
+use ecolor::Rgba;
+use embedded_graphics::{
+    mono_font::{iso_8859_1::{FONT_10X20, FONT_4X6, FONT_5X7, FONT_6X10, FONT_8X13}, MonoFont, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, OriginDimensions, Point, Size},
+    text::{renderer::TextRenderer, Baseline, Text},
+    Drawable,
+};
+use glam::{UVec2, Vec2};
+
+use crate::{draw::DrawContext, effect::{parse_color, Effect, Pixel}};
+
+/// The built in fonts, by name
+const FONTS: &[(&str, &MonoFont)] = &[
+    ("4x6", &FONT_4X6),
+    ("5x7", &FONT_5X7),
+    ("6x10", &FONT_6X10),
+    ("8x13", &FONT_8X13),
+    ("10x20", &FONT_10X20),
+];
+
+/// Which way the text moves across the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    Left,
+    Right,
+    Up,
+    Down,
+    /// Stays in the center
+    None,
+}
+
+impl Direction {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "left" => Some(Direction::Left),
+            "right" => Some(Direction::Right),
+            "up" => Some(Direction::Up),
+            "down" => Some(Direction::Down),
+            "none" => Some(Direction::None),
+            _ => None,
+        }
+    }
+}
+
+/// The rendered text, one bool per font pixel row by row
+#[derive(Debug, Clone, Default)]
+struct Bitmap {
+    size: UVec2,
+    lit: Vec<bool>,
+}
+
+impl Bitmap {
+    fn render(text: &str, font: &MonoFont) -> Self {
+        let style = MonoTextStyle::new(font, BinaryColor::On);
+        let size = style.measure_string(text, Point::zero(), Baseline::Top).bounding_box.size;
+
+        let mut bitmap = Bitmap {
+            size: UVec2::new(size.width, size.height),
+            lit: vec![false; (size.width * size.height) as usize],
+        };
+
+        Text::with_baseline(text, Point::zero(), style, Baseline::Top).draw(&mut bitmap).unwrap();
+        bitmap
+    }
+
+    fn is_lit(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x >= self.size.x as i32 || y >= self.size.y as i32 {
+            return false;
+        }
+
+        self.lit[y as usize * self.size.x as usize + x as usize]
+    }
+}
+
+impl OriginDimensions for Bitmap {
+    fn size(&self) -> Size {
+        Size::new(self.size.x, self.size.y)
+    }
+}
+
+impl DrawTarget for Bitmap {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = embedded_graphics::Pixel<BinaryColor>>,
+    {
+        for embedded_graphics::Pixel(point, color) in pixels {
+            if point.x >= 0 && point.y >= 0 && (point.x as u32) < self.size.x && (point.y as u32) < self.size.y {
+                self.lit[point.y as usize * self.size.x as usize + point.x as usize] = color.is_on();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A message in a bitmap font, scrolling across the canvas like a ticker
+#[derive(Debug, Clone)]
+pub struct TextEffect {
+    text: String,
+    font: &'static MonoFont<'static>,
+    bitmap: Bitmap,
+    direction: Direction,
+    /// In canvas units per second
+    speed: f32,
+    /// Canvas units per font pixel
+    size: f32,
+    /// Moves the text away from the center of the canvas, in canvas units
+    offset: Vec2,
+    color: Rgba,
+    /// Fades from `color` at the start of the text to this at the end
+    gradient: Option<Rgba>,
+    brightness: f32,
+    /// How far the text has scrolled, in canvas units
+    travel: f32,
+    last_update: Option<f32>,
+    /// Top left corner of the text this frame
+    corner: Vec2,
+}
+
+impl Default for TextEffect {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            font: &FONT_5X7,
+            bitmap: Bitmap::default(),
+            direction: Direction::default(),
+            speed: 10.0,
+            size: 1.0,
+            offset: Vec2::ZERO,
+            color: Rgba::WHITE,
+            gradient: None,
+            brightness: 1.0,
+            travel: 0.0,
+            last_update: None,
+            corner: Vec2::ZERO,
+        }
+    }
+}
+
+impl TextEffect {
+    fn set_text(&mut self, text: &str, font: &'static MonoFont<'static>) {
+        // the same message is sent every frame, so only new ones start over
+        if text == self.text && std::ptr::eq(font, self.font) {
+            return;
+        }
+
+        self.text = text.to_owned();
+        self.font = font;
+        self.bitmap = Bitmap::render(text, font);
+        self.travel = 0.0;
+    }
+
+    /// Top left corner of the text, scrolled in from the edge of the canvas
+    fn corner(&self, bounds: (Vec2, Vec2)) -> Vec2 {
+        let (min, max) = bounds;
+        let text_size = self.bitmap.size.as_vec2() * self.size;
+        let centered = (min + max - text_size) / 2.0 + self.offset;
+
+        // from fully outside on one side to fully outside on the other
+        let span = max - min + text_size;
+        let scrolled = |span: f32| if span > 0.0 { self.travel.rem_euclid(span) } else { 0.0 };
+
+        match self.direction {
+            Direction::Left => Vec2::new(max.x - scrolled(span.x), centered.y),
+            Direction::Right => Vec2::new(min.x - text_size.x + scrolled(span.x), centered.y),
+            Direction::Up => Vec2::new(centered.x, max.y - scrolled(span.y)),
+            Direction::Down => Vec2::new(centered.x, min.y - text_size.y + scrolled(span.y)),
+            Direction::None => centered,
+        }
+    }
+}
+
+impl Effect for TextEffect {
+    fn name(&self) -> &str {
+        "text"
+    }
+
+    fn update(&mut self, ctx: &DrawContext) {
+        let delta = self.last_update.map_or(0.0, |last_update| (ctx.elapsed_seconds - last_update).max(0.0));
+        self.last_update = Some(ctx.elapsed_seconds);
+
+        self.travel += delta * self.speed;
+        self.corner = self.corner(ctx.bounds);
+    }
+
+    fn render_pixel(&self, _ctx: &DrawContext, pixel: &Pixel) -> Rgba {
+        let font_pos = ((pixel.pos - self.corner) / self.size).floor();
+
+        if !self.bitmap.is_lit(font_pos.x as i32, font_pos.y as i32) {
+            return Rgba::TRANSPARENT;
+        }
+
+        let color = match self.gradient {
+            Some(gradient) => {
+                let t = font_pos.x / self.bitmap.size.x.saturating_sub(1).max(1) as f32;
+                self.color * (1.0 - t) + gradient * t
+            },
+            None => self.color,
+        };
+
+        color * self.brightness
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "speed" => self.speed = value,
+            "size" => self.size = value.max(f32::EPSILON),
+            "x" => self.offset.x = value,
+            "y" => self.offset.y = value,
+            "brightness" => self.brightness = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_text_param(&mut self, name: &str, value: &str) -> bool {
+        let font = FONTS.iter().find(|(font_name, _)| *font_name == value).map(|(_, font)| *font);
+
+        match (name, font, Direction::parse(value), parse_color(value)) {
+            ("text", _, _, _) => self.set_text(value, self.font),
+            ("font", Some(font), _, _) => self.set_text(&self.text.clone(), font),
+            ("direction", _, Some(direction), _) => self.direction = direction,
+            ("color", _, _, Some(color)) => self.color = color,
+            ("gradient", _, _, Some(color)) => self.gradient = Some(color),
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Rgba;
+    use glam::{Vec2, Vec3};
+
+    use super::TextEffect;
+    use crate::{draw::{test_context, DrawContext}, effect::{Effect, Pixel}};
+
+    #[test]
+    fn ticker() {
+        let mut effect = TextEffect::default();
+        assert!(effect.set_text_param("text", "I"));
+        assert!(effect.set_text_param("color", "#ff0000"));
+        assert!(!effect.set_text_param("font", "3x3"));
+        assert_eq!(effect.bitmap.size, [5, 7].into());
+
+        let ctx = |seconds| DrawContext { bounds: (Vec2::new(0.0, 0.0), Vec2::new(20.0, 7.0)), ..test_context(seconds) };
+        // the middle of the I
+        let pixel = |x| Pixel { index: 0, pos: Vec2::new(x, 3.5), pos_3d: Vec3::ZERO };
+
+        // starts just outside the right edge and moves left at 10 units per second
+        effect.update(&ctx(0.0));
+        assert_eq!(effect.render_pixel(&ctx(0.0), &pixel(19.5)), Rgba::TRANSPARENT);
+
+        effect.update(&ctx(1.0));
+        assert_eq!(effect.render_pixel(&ctx(1.0), &pixel(12.5)), Rgba::RED);
+        assert_eq!(effect.render_pixel(&ctx(1.0), &pixel(11.5)), Rgba::TRANSPARENT);
+
+        // a new message starts over
+        assert!(effect.set_text_param("text", "II"));
+        effect.update(&ctx(1.0));
+        assert_eq!(effect.render_pixel(&ctx(1.0), &pixel(12.5)), Rgba::TRANSPARENT);
+    }
+}
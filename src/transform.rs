@@ -0,0 +1,134 @@
+use glam::{Affine2, Vec2, Vec3};
+
+/// Placement of a fixture in the real world, in mm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform2D {
+    /// Where the fixture origin (pixel 0,0 corner) sits
+    pub translation: Vec2,
+    /// Rotation around the fixture origin in radians
+    pub rotation: f32,
+    pub scale: Vec2,
+}
+
+impl Default for Transform2D {
+    fn default() -> Self {
+        Self {
+            translation: Vec2::ZERO,
+            rotation: 0.0,
+            scale: Vec2::ONE,
+        }
+    }
+}
+
+impl Transform2D {
+    pub fn from_translation(translation: Vec2) -> Self {
+        Self {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn affine(&self) -> Affine2 {
+        Affine2::from_scale_angle_translation(self.scale, self.rotation, self.translation)
+    }
+
+    /// Transform a point from fixture space into world space
+    pub fn transform_point(&self, point: Vec2) -> Vec2 {
+        self.affine().transform_point2(point)
+    }
+}
+
+/// The 3d surface a fixture is mounted on.
+/// The 2d world layout is the unrolled view of this surface,
+/// so 2d effects keep working off the layout while 3d effects get the real shape.
+/// x is right, y is down and z points into the head, all in mm.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Surface {
+    /// On the front plane (z = 0)
+    #[default]
+    Flat,
+    /// Wrapped around a vertical cylinder that touches the front plane at `axis_x`
+    Cylinder { axis_x: f32, radius: f32 },
+}
+
+impl Surface {
+    /// Lift a 2d world position onto the surface
+    pub fn lift(&self, world_pos: Vec2) -> Vec3 {
+        match *self {
+            Surface::Flat => world_pos.extend(0.0),
+            Surface::Cylinder { axis_x, radius } => {
+                let angle = (world_pos.x - axis_x) / radius;
+
+                Vec3::new(
+                    axis_x + radius * angle.sin(),
+                    world_pos.y,
+                    radius * (1.0 - angle.cos()),
+                )
+            }
+        }
+    }
+}
+
+/// The shared space that effects are drawn in.
+/// World positions (mm) are shifted by `origin` and divided by `unit_size`,
+/// so effects don't need to care about physical dimensions.
+#[derive(Debug, Clone, Copy)]
+pub struct Canvas {
+    /// World position (mm) of canvas 0,0
+    pub origin: Vec2,
+    /// Size of one canvas unit in mm
+    pub unit_size: f32,
+}
+
+impl Canvas {
+    pub fn new(origin: Vec2, unit_size: f32) -> Self {
+        Self { origin, unit_size }
+    }
+
+    pub fn to_canvas(self, world_pos: Vec2) -> Vec2 {
+        (world_pos - self.origin) / self.unit_size
+    }
+
+    pub fn to_canvas_3d(self, world_pos: Vec3) -> Vec3 {
+        (world_pos - self.origin.extend(0.0)) / self.unit_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use glam::{Vec2, Vec3};
+
+    use super::{Canvas, Surface, Transform2D};
+
+    #[test]
+    fn transform_point() {
+        let transform = Transform2D {
+            translation: Vec2::new(100.0, 0.0),
+            rotation: FRAC_PI_2,
+            scale: Vec2::splat(2.0),
+        };
+
+        let pos = transform.transform_point(Vec2::new(10.0, 0.0));
+        assert!(pos.abs_diff_eq(Vec2::new(100.0, 20.0), 1e-4));
+    }
+
+    #[test]
+    fn canvas() {
+        let canvas = Canvas::new(Vec2::new(80.0, 80.0), 10.0);
+        assert_eq!(canvas.to_canvas(Vec2::new(80.0, 80.0)), Vec2::ZERO);
+        assert_eq!(canvas.to_canvas(Vec2::new(0.0, 100.0)), Vec2::new(-8.0, 2.0));
+    }
+
+    #[test]
+    fn cylinder() {
+        let surface = Surface::Cylinder { axis_x: 0.0, radius: 100.0 };
+
+        assert_eq!(surface.lift(Vec2::new(0.0, 5.0)), Vec3::new(0.0, 5.0, 0.0));
+
+        // a quarter turn around the side of the cylinder
+        let side = surface.lift(Vec2::new(100.0 * FRAC_PI_2, 5.0));
+        assert!(side.abs_diff_eq(Vec3::new(100.0, 5.0, 100.0), 1e-3));
+    }
+}
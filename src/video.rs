@@ -0,0 +1,237 @@
+use std::{collections::HashMap, fs::File, io::{BufReader, ErrorKind, Read}, path::{Path, PathBuf}, process::{Command, Stdio}, sync::{Arc, LazyLock, Mutex, OnceLock}};
+
+use ecolor::Rgba;
+use glam::UVec2;
+use y4m::Colorspace;
+
+use crate::image_effect::{self, ImageFrame};
+
+/// Files that are played as videos, everything but y4m is converted by ffmpeg
+pub const VIDEO_EXTENSIONS: &[&str] = &["y4m", "mp4", "mov", "mkv", "webm", "avi"];
+
+/// Videos and animations are scaled down to at most this many pixels on their longest
+/// side, the whole clip is kept in memory
+const MAX_SIZE: u32 = 96;
+
+/// Bytes of frames kept per clip, longer ones are cut off. At the largest size that's
+/// about 15 seconds at 30 frames per second.
+pub const MAX_MEMORY: usize = 64 << 20;
+
+/// A decoded video or image, shared by every effect that plays it
+#[derive(Debug)]
+pub struct Clip {
+    pub size: UVec2,
+    pub frames: Arc<Vec<ImageFrame>>,
+    /// In seconds
+    pub duration: f32,
+}
+
+/// Filled in by the worker thread once the file is decoded
+pub type PendingClip = Arc<OnceLock<Result<Clip, String>>>;
+
+/// Every file that was played, so playing one again doesn't decode it again
+static CLIPS: LazyLock<Mutex<HashMap<PathBuf, PendingClip>>> = LazyLock::new(Default::default);
+
+/// How many frames of a size fit in `max_memory`
+pub fn max_frames(size: UVec2, max_memory: usize) -> usize {
+    let frame_bytes = (size.x * size.y) as usize * std::mem::size_of::<Rgba>();
+    (max_memory / frame_bytes.max(1)).max(1)
+}
+
+/// BT.601 with limited range, like most video
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> Rgba {
+    let y = 1.164 * (y as f32 - 16.0);
+    let (u, v) = (u as f32 - 128.0, v as f32 - 128.0);
+
+    let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+
+    Rgba::from_srgba_unmultiplied(
+        channel(y + 1.596 * v),
+        channel(y - 0.392 * u - 0.813 * v),
+        channel(y + 2.017 * u),
+        255,
+    )
+}
+
+/// The factor a frame of `size` is scaled down by to fit in `MAX_SIZE`, and its size after
+pub fn scaled_size(size: UVec2) -> (u32, UVec2) {
+    let factor = size.max_element().div_ceil(MAX_SIZE).max(1);
+    (factor, (size + factor - 1) / factor)
+}
+
+/// Averages blocks of `factor` by `factor` pixels, into an image of `small_size`
+pub fn downsample(pixels: Vec<Rgba>, size: UVec2, factor: u32, small_size: UVec2) -> Vec<Rgba> {
+    if factor <= 1 {
+        return pixels;
+    }
+
+    let mut sums = vec![(Rgba::TRANSPARENT, 0.0); (small_size.x * small_size.y) as usize];
+
+    for (i, pixel) in pixels.iter().enumerate() {
+        let (x, y) = (i as u32 % size.x, i as u32 / size.x);
+        let (sum, count) = &mut sums[((y / factor) * small_size.x + x / factor) as usize];
+
+        *sum = *sum + *pixel;
+        *count += 1.0;
+    }
+
+    sums.into_iter().map(|(sum, count)| sum * (1.0 / count)).collect()
+}
+
+/// Decodes an 8 bit y4m stream into frames scaled down to `MAX_SIZE`, until the end or
+/// until the frames take up `max_memory`
+fn decode_y4m(reader: impl Read, max_memory: usize) -> Result<(UVec2, Vec<ImageFrame>, f32), String> {
+    let mut decoder = y4m::decode(reader).map_err(|err| err.to_string())?;
+
+    let size = UVec2::new(decoder.get_width() as u32, decoder.get_height() as u32);
+    let framerate = decoder.get_framerate();
+    let frame_duration = framerate.den as f32 / framerate.num.max(1) as f32;
+
+    // chroma samples are shared by this many pixels horizontally and vertically
+    let subsampling = match decoder.get_colorspace() {
+        Colorspace::Cmono => None,
+        Colorspace::C420 | Colorspace::C420jpeg | Colorspace::C420paldv | Colorspace::C420mpeg2 => Some((2, 2)),
+        Colorspace::C422 => Some((2, 1)),
+        Colorspace::C444 => Some((1, 1)),
+        other => return Err(format!("Only 8 bit videos are supported, not {other:?}")),
+    };
+
+    let (factor, small_size) = scaled_size(size);
+    let mut frames = vec![];
+
+    while frames.len() < max_frames(small_size, max_memory) {
+        let frame = match decoder.read_frame() {
+            Ok(frame) => frame,
+            Err(y4m::Error::EOF) => break,
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let pixels: Vec<_> = (0..size.y).flat_map(|y| (0..size.x).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let luma = frame.get_y_plane()[(y * size.x + x) as usize];
+
+                let Some((sub_x, sub_y)) = subsampling else {
+                    return yuv_to_rgb(luma, 128, 128);
+                };
+
+                let chroma_width = size.x.div_ceil(sub_x);
+                let chroma = ((y / sub_y) * chroma_width + x / sub_x) as usize;
+                yuv_to_rgb(luma, frame.get_u_plane()[chroma], frame.get_v_plane()[chroma])
+            })
+            .collect();
+
+        let pixels = downsample(pixels, size, factor, small_size);
+        frames.push(ImageFrame { pixels, start: frames.len() as f32 * frame_duration });
+    }
+
+    let duration = frames.len() as f32 * frame_duration;
+    Ok((small_size, frames, duration))
+}
+
+/// Decodes a video into frames with their start times, and the length of the video
+fn decode(path: &Path) -> Result<(UVec2, Vec<ImageFrame>, f32), String> {
+    let is_y4m = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("y4m"));
+
+    if is_y4m {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        return decode_y4m(BufReader::new(file), MAX_MEMORY);
+    }
+
+    // ffmpeg scales the video down as well, so less has to go through the pipe
+    let scale = format!("scale=w='min({MAX_SIZE},iw)':h='min({MAX_SIZE},ih)':force_original_aspect_ratio=decrease");
+
+    let mut ffmpeg = Command::new("ffmpeg")
+        .args(["-v", "error", "-i"])
+        .arg(path)
+        .args(["-an", "-vf", &scale, "-pix_fmt", "yuv444p", "-f", "yuv4mpegpipe", "-"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| match err.kind() {
+            ErrorKind::NotFound => "Playing videos needs ffmpeg to be installed, or convert the video to .y4m".to_owned(),
+            _ => format!("Could not start ffmpeg: {err}"),
+        })?;
+
+    let decoded = decode_y4m(BufReader::new(ffmpeg.stdout.take().unwrap()), MAX_MEMORY);
+
+    // ffmpeg is still going if the video was cut off
+    let cut_off = decoded.as_ref().is_ok_and(|(size, frames, _)| frames.len() >= max_frames(*size, MAX_MEMORY));
+    if cut_off {
+        ffmpeg.kill().ok();
+    }
+
+    let status = ffmpeg.wait().map_err(|err| err.to_string())?;
+
+    if !status.success() && !cut_off {
+        return Err(format!("ffmpeg failed with {status}"));
+    }
+
+    decoded
+}
+
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| VIDEO_EXTENSIONS.iter().any(|video| extension.eq_ignore_ascii_case(video)))
+}
+
+/// Starts decoding a video or image on a worker thread, so the render loop doesn't stall.
+/// Files that were loaded before are shared.
+pub fn load(path: &Path) -> PendingClip {
+    let mut clips = CLIPS.lock().unwrap();
+
+    clips.entry(path.to_owned())
+        .or_insert_with(|| {
+            let pending = PendingClip::default();
+            let (path, result) = (path.to_owned(), pending.clone());
+
+            std::thread::spawn(move || {
+                let decoded = match is_video(&path) {
+                    true => decode(&path),
+                    false => image_effect::decode(&path),
+                };
+
+                let clip = decoded.and_then(|(size, frames, duration)| match frames.is_empty() {
+                    true => Err("There are no frames in it".to_owned()),
+                    false => Ok(Clip { size, frames: Arc::new(frames), duration }),
+                });
+
+                result.set(clip).ok();
+            });
+
+            pending
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use ecolor::Rgba;
+    use y4m::{Colorspace, Frame, Ratio};
+
+    use super::{decode_y4m, MAX_MEMORY};
+
+    #[test]
+    fn y4m() {
+        let mut file = vec![];
+        let mut encoder = y4m::encode(4, 2, Ratio::new(25, 1))
+            .with_colorspace(Colorspace::C420)
+            .write_header(&mut file)
+            .unwrap();
+
+        // white on the left half and black on the right, then all black
+        encoder.write_frame(&Frame::new([&[235, 235, 16, 16, 235, 235, 16, 16], &[128, 128], &[128, 128]], None)).unwrap();
+        encoder.write_frame(&Frame::new([&[16; 8], &[128, 128], &[128, 128]], None)).unwrap();
+
+        let (size, frames, duration) = decode_y4m(file.as_slice(), MAX_MEMORY).unwrap();
+
+        assert_eq!(size, [4, 2].into());
+        assert_eq!(duration, 0.08);
+        assert_eq!(frames[1].start, 0.04);
+        assert_eq!(frames[0].pixels[0], Rgba::WHITE);
+        assert_eq!(frames[0].pixels[3], Rgba::BLACK);
+        assert_eq!(frames[1].pixels[0], Rgba::BLACK);
+
+        // cut off once the frames fill the memory
+        let (_, frames, duration) = decode_y4m(file.as_slice(), 8 * size_of::<Rgba>()).unwrap();
+        assert_eq!((frames.len(), duration), (1, 0.04));
+    }
+}